@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS chat_messages
+(
+    id      TEXT PRIMARY KEY,
+    room_id INTEGER NOT NULL,
+    user    TEXT    NOT NULL,
+    message TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS chat_messages_room_id ON chat_messages (room_id, id);
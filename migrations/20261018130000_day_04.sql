@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS reindeer
+(
+    name             TEXT PRIMARY KEY,
+    strength         INTEGER NOT NULL,
+    speed            REAL    NOT NULL,
+    height           INTEGER NOT NULL,
+    antler_width     INTEGER NOT NULL,
+    snow_magic_power INTEGER NOT NULL,
+    favorite_food    TEXT    NOT NULL,
+    candies          INTEGER NOT NULL
+);
@@ -0,0 +1,55 @@
+-- Rebuilds Day 18's tables with constraints, keeping the rows that satisfy them.
+-- Orders without a region were Day 13's, which used to share the table, and
+-- move to a table of their own.
+ALTER TABLE orders RENAME TO orders_old;
+ALTER TABLE regions RENAME TO regions_old;
+
+CREATE TABLE regions
+(
+    id   INTEGER PRIMARY KEY,
+    name TEXT NOT NULL CHECK (name <> '')
+);
+
+INSERT INTO regions (id, name)
+SELECT id, name
+FROM regions_old
+WHERE id IS NOT NULL AND name <> '';
+
+CREATE TABLE orders
+(
+    id        INTEGER PRIMARY KEY,
+    region_id INTEGER NOT NULL REFERENCES regions (id),
+    gift_name TEXT    NOT NULL CHECK (gift_name <> ''),
+    quantity  INTEGER NOT NULL CHECK (quantity > 0)
+);
+
+CREATE INDEX orders_region_id ON orders (region_id);
+
+INSERT INTO orders (id, region_id, gift_name, quantity)
+SELECT id, region_id, gift_name, quantity
+FROM orders_old
+WHERE id IS NOT NULL
+  AND region_id IN (SELECT id FROM regions)
+  AND gift_name <> ''
+  AND quantity > 0;
+
+-- Day 13 has no regions, so its orders can't reference them and live apart.
+CREATE TABLE day13_orders
+(
+    id        INTEGER PRIMARY KEY,
+    region_id INTEGER NOT NULL,
+    gift_name TEXT    NOT NULL CHECK (gift_name <> ''),
+    quantity  INTEGER NOT NULL CHECK (quantity > 0)
+);
+
+INSERT INTO day13_orders (id, region_id, gift_name, quantity)
+SELECT id, region_id, gift_name, quantity
+FROM orders_old
+WHERE id IS NOT NULL
+  AND region_id IS NOT NULL
+  AND region_id NOT IN (SELECT id FROM regions)
+  AND gift_name <> ''
+  AND quantity > 0;
+
+DROP TABLE orders_old;
+DROP TABLE regions_old;
@@ -0,0 +1,12 @@
+-- When an order was placed. Orders from before this migration have no time and
+-- are left out of the time series.
+ALTER TABLE orders ADD COLUMN ordered_at TEXT;
+ALTER TABLE day13_orders ADD COLUMN ordered_at TEXT;
+
+-- The ULID an order was submitted under, if any. Its timestamp is the order's.
+ALTER TABLE orders ADD COLUMN ulid TEXT;
+ALTER TABLE day13_orders ADD COLUMN ulid TEXT;
+
+CREATE UNIQUE INDEX orders_ulid ON orders (ulid);
+CREATE UNIQUE INDEX day13_orders_ulid ON day13_orders (ulid);
+CREATE INDEX orders_ordered_at ON orders (ordered_at);
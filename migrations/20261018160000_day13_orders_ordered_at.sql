@@ -0,0 +1,2 @@
+-- Day 13 reports its orders over time as well.
+CREATE INDEX day13_orders_ordered_at ON day13_orders (ordered_at);
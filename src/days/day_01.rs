@@ -1,15 +1,41 @@
 use axum::{extract::Path, routing::get, Json, Router};
 
+use crate::error::AppError;
+
 pub(super) fn route() -> Router {
     Router::new().route("/*numbers", get(numbers))
 }
 
-async fn numbers(Path(numbers): Path<String>) -> Json<i64> {
-    numbers
+async fn numbers(Path(numbers): Path<String>) -> Result<Json<i64>, AppError> {
+    Ok(numbers
         .trim_end_matches('/')
         .split('/')
-        .map(|x| x.parse::<i64>().unwrap())
-        .fold(0, std::ops::BitXor::bitxor)
-        .pow(3)
-        .into()
-}
\ No newline at end of file
+        .map(str::parse::<i64>)
+        .try_fold(0, |acc, x| x.map(|x| acc ^ x))?
+        .checked_pow(3)
+        .ok_or_else(|| AppError::bad_request("result overflows i64"))?
+        .into())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::days::routes_test;
+
+    #[tokio::test]
+    async fn task1() {
+        routes_test().await.get("/1/4/8").await.assert_json(&1728);
+    }
+
+    #[tokio::test]
+    async fn malformed() {
+        let server = routes_test().await;
+        let res = server.get("/1/4/elf").expect_failure().await;
+        res.assert_status_bad_request();
+        res.assert_json_contains(&serde_json::json!({"status": 400}));
+        server
+            .get("/1/9223372036854775807")
+            .expect_failure()
+            .await
+            .assert_status_bad_request();
+    }
+}
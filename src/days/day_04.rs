@@ -1,17 +1,37 @@
-use axum::routing::post;
+use std::collections::BTreeMap;
+
+use axum::extract::Query;
+use axum::routing::{get, post};
 use axum::{Json, Router};
+use sqlx::SqlitePool;
+
+use crate::{error::AppError, records::Records};
+
+mod field;
+mod herd;
+mod stats;
 
-pub(super) fn route() -> Router {
+pub(super) fn route(pool: SqlitePool) -> Router {
     Router::new()
         .route("/strength", post(strength))
-        .route("/contest", post(contest))
+        .route("/contest", get(herd::stored_contest).post(ad_hoc_contest))
+        .route("/stats", get(stats::stored).post(stats::ad_hoc))
+        .route("/herd", get(herd::list))
+        .route(
+            "/herd/:name",
+            get(herd::get).put(herd::put).delete(herd::delete),
+        )
+        .route("/leaderboard/:stat", get(herd::leaderboard))
+        .with_state(pool)
 }
 
-async fn strength(Json(payload): Json<Vec<Reindeer>>) -> Json<i64> {
-    payload.into_iter().map(|x| x.strength).sum::<i64>().into()
+async fn strength(records: Records) -> Result<Json<i64>, AppError> {
+    let payload: Vec<Reindeer> = records.collect().await?;
+    validate(&payload)?;
+    Ok(payload.into_iter().map(|x| x.strength).sum::<i64>().into())
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize, sqlx::FromRow)]
 struct Reindeer {
     #[serde(default)]
     name: String,
@@ -26,10 +46,52 @@ struct Reindeer {
     snow_magic_power: i64,
     #[serde(default)]
     favorite_food: String,
-    #[serde(default, rename(deserialize = "cAnD13s_3ATeN-yesT3rdAy"))]
+    #[serde(
+        default,
+        rename(deserialize = "cAnD13s_3ATeN-yesT3rdAy"),
+        alias = "candies"
+    )]
     candies: i64,
 }
 
+/// Rejects stats no reindeer could have: negative numbers, or a NaN or
+/// infinite speed.
+fn validate(herd: &[Reindeer]) -> Result<(), AppError> {
+    for (i, reindeer) in herd.iter().enumerate() {
+        for field in field::Field::NUMERIC {
+            let value = field.of(reindeer).as_f64().unwrap_or_default();
+            if !value.is_finite() || value < 0.0 {
+                return Err(AppError::Unprocessable(format!(
+                    "reindeer {i} ({}): {} must be a non-negative number",
+                    reindeer.name,
+                    field.column()
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct ContestQuery {
+    /// Comma separated fields to compete in instead of the four classic ones.
+    categories: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum Contest {
+    Classic(ContestOutput),
+    Categories(BTreeMap<field::Field, field::Winners>),
+}
+
+fn contest(herd: &[Reindeer], q: ContestQuery) -> Result<Contest, AppError> {
+    Ok(match q.categories {
+        None => Contest::Classic(contest_of(herd)),
+        Some(list) => Contest::Categories(field::winners(herd, &field::Field::parse_list(&list)?)),
+    })
+}
+
 #[derive(Default, serde::Serialize)]
 struct ContestOutput {
     fastest: String,
@@ -38,12 +100,22 @@ struct ContestOutput {
     consumer: String,
 }
 
-async fn contest(Json(payload): Json<Vec<Reindeer>>) -> Json<ContestOutput> {
+async fn ad_hoc_contest(
+    Query(q): Query<ContestQuery>,
+    records: Records,
+) -> Result<Json<Contest>, AppError> {
+    let payload: Vec<Reindeer> = records.collect().await?;
+    validate(&payload)?;
+    contest(&payload, q).map(Json)
+}
+
+/// Earlier reindeer win ties.
+fn contest_of(payload: &[Reindeer]) -> ContestOutput {
     let Some(first) = payload.first() else {
-        return ContestOutput::default().into();
+        return ContestOutput::default();
     };
     let (mut fastest, mut tallest, mut magician, mut consumer) = (first, first, first, first);
-    for x in &payload {
+    for x in payload {
         if fastest.speed < x.speed {
             fastest = x;
         }
@@ -57,7 +129,7 @@ async fn contest(Json(payload): Json<Vec<Reindeer>>) -> Json<ContestOutput> {
             consumer = x;
         }
     }
-    Json(ContestOutput {
+    ContestOutput {
         fastest: format!(
             "Speeding past the finish line with a strength of {} is {}",
             fastest.strength, fastest.name,
@@ -74,6 +146,270 @@ async fn contest(Json(payload): Json<Vec<Reindeer>>) -> Json<ContestOutput> {
             "{} ate lots of candies, but also some {}",
             consumer.name, consumer.favorite_food,
         ),
-    })
+    }
 }
 
+#[cfg(test)]
+mod test {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use crate::days::routes_test;
+
+    fn reindeer(speed: f64, height: i64, magic: i64, candies: i64) -> serde_json::Value {
+        json!({
+            "strength": 5,
+            "speed": speed,
+            "height": height,
+            "antler_width": 10,
+            "snow_magic_power": magic,
+            "favorite_food": "hay",
+            "cAnD13s_3ATeN-yesT3rdAy": candies
+        })
+    }
+
+    #[tokio::test]
+    async fn herd_crud() {
+        let server = routes_test().await;
+        server
+            .put("/4/herd/Dasher")
+            .json(&reindeer(50.4, 80, 9001, 2))
+            .await
+            .assert_status(StatusCode::CREATED);
+        server
+            .put("/4/herd/Dasher")
+            .json(&reindeer(60.0, 80, 9001, 2))
+            .await
+            .assert_status_ok();
+        server
+            .get("/4/herd/Dasher")
+            .await
+            .assert_json_contains(&json!({"name": "Dasher", "speed": 60.0, "candies": 2}));
+        server
+            .get("/4/herd")
+            .await
+            .assert_json_contains(&json!([{"name": "Dasher"}]));
+
+        server
+            .delete("/4/herd/Dasher")
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        server
+            .delete("/4/herd/Dasher")
+            .expect_failure()
+            .await
+            .assert_status_not_found();
+        server
+            .get("/4/herd/Dasher")
+            .expect_failure()
+            .await
+            .assert_status_not_found();
+    }
+
+    #[tokio::test]
+    async fn leaderboard_ties_and_pages() {
+        let server = routes_test().await;
+        for (name, candies) in [("Comet", 3), ("Blitzen", 7), ("Vixen", 7), ("Cupid", 1)] {
+            server
+                .put(&format!("/4/herd/{name}"))
+                .json(&reindeer(1.5, 100, 1, candies))
+                .await;
+        }
+        server
+            .get("/4/leaderboard/candies?limit=3")
+            .await
+            .assert_json(&json!({
+                "stat": "candies",
+                "total": 4,
+                "entries": [
+                    {"rank": 1, "name": "Blitzen", "value": 7},
+                    {"rank": 1, "name": "Vixen", "value": 7},
+                    {"rank": 3, "name": "Comet", "value": 3}
+                ]
+            }));
+        server
+            .get("/4/leaderboard/candies?limit=3&offset=3")
+            .await
+            .assert_json_contains(&json!({"entries": [{"rank": 4, "name": "Cupid"}]}));
+        server
+            .get("/4/leaderboard/speed?limit=1")
+            .await
+            .assert_json_contains(&json!({"entries": [{"rank": 1, "value": 1.5}]}));
+        server
+            .get("/4/leaderboard/name")
+            .expect_failure()
+            .await
+            .assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn contest_from_herd() {
+        let server = routes_test().await;
+        server
+            .get("/4/contest")
+            .await
+            .assert_json(&json!({"fastest": "", "tallest": "", "magician": "", "consumer": ""}));
+        server
+            .put("/4/herd/Dasher")
+            .json(&reindeer(8.691, 150, 9001, 1))
+            .await;
+        server
+            .put("/4/herd/Prancer")
+            .json(&reindeer(9.0, 120, 7, 100))
+            .await;
+        let stored = server.get("/4/contest").await.json::<serde_json::Value>();
+        assert_eq!(
+            stored,
+            json!({
+                "fastest": "Speeding past the finish line with a strength of 5 is Prancer",
+                "tallest": "Dasher is standing tall with his 10 cm wide antlers",
+                "magician": "Dasher could blast you away with a snow magic power of 9001",
+                "consumer": "Prancer ate lots of candies, but also some hay"
+            })
+        );
+
+        // an ad-hoc herd is scored the same way, without touching the stored one
+        let mut dasher = reindeer(8.691, 150, 9001, 1);
+        dasher["name"] = "Dasher".into();
+        let mut prancer = reindeer(9.0, 120, 7, 100);
+        prancer["name"] = "Prancer".into();
+        server
+            .post("/4/contest")
+            .json(&json!([dasher, prancer]))
+            .await
+            .assert_json(&stored);
+    }
+
+    #[tokio::test]
+    async fn categories_list_ties() {
+        let server = routes_test().await;
+        let mut dasher = reindeer(9.0, 150, 9001, 1);
+        dasher["name"] = "Dasher".into();
+        let mut prancer = reindeer(9.0, 120, 7, 100);
+        prancer["name"] = "Prancer".into();
+        server
+            .post("/4/contest?categories=speed,height,favorite_food")
+            .json(&json!([dasher, prancer]))
+            .await
+            .assert_json(&json!({
+                "speed": {"value": 9.0, "names": ["Dasher", "Prancer"]},
+                "height": {"value": 150, "names": ["Dasher"]},
+                "favorite_food": {"value": "hay", "names": ["Dasher", "Prancer"]}
+            }));
+        server
+            .get("/4/contest?categories=candies")
+            .await
+            .assert_json(&json!({"candies": {"value": null, "names": []}}));
+        server
+            .get("/4/contest?categories=wings")
+            .expect_failure()
+            .await
+            .assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        let server = routes_test().await;
+        server
+            .get("/4/stats")
+            .await
+            .assert_json(&json!({"count": 0, "fields": {}}));
+        for (name, speed, candies) in [("Comet", 1.0, 2), ("Cupid", 2.0, 4), ("Vixen", 6.0, 9)] {
+            server
+                .put(&format!("/4/herd/{name}"))
+                .json(&reindeer(speed, 100, 1, candies))
+                .await;
+        }
+        let stats = server.get("/4/stats").await.json::<serde_json::Value>();
+        assert_eq!(stats["count"], 3);
+        assert_eq!(
+            stats["fields"]["speed"],
+            json!({"min": 1.0, "max": 6.0, "mean": 3.0, "median": 2.0, "stddev": (14f64 / 3.0).sqrt()})
+        );
+        assert_eq!(
+            stats["fields"]["candies"],
+            json!({"min": 2, "max": 9, "mean": 5.0, "median": 4.0, "stddev": (26f64 / 3.0).sqrt()})
+        );
+        assert_eq!(stats["fields"]["height"]["stddev"], 0.0);
+        assert!(stats["fields"].get("name").is_none());
+
+        // an ad-hoc herd, with an even count
+        server
+            .post("/4/stats")
+            .json(&json!([reindeer(1.0, 1, 1, 1), reindeer(2.0, 2, 2, 2)]))
+            .await
+            .assert_json_contains(&json!({"count": 2, "fields": {"height": {"median": 1.5}}}));
+    }
+
+    #[tokio::test]
+    async fn impossible_stats() {
+        let server = routes_test().await;
+        let negative = reindeer(1.0, -3, 1, 1);
+        for path in ["/4/strength", "/4/contest", "/4/stats"] {
+            let res = server
+                .post(path)
+                .json(&json!([reindeer(1.0, 1, 1, 1), negative]))
+                .expect_failure()
+                .await;
+            res.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+            res.assert_json_contains(
+                &json!({"detail": "reindeer 1 (): height must be a non-negative number"}),
+            );
+        }
+        server
+            .put("/4/herd/Dasher")
+            .json(&reindeer(-0.5, 1, 1, 1))
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        // json has no NaN, and a quoted one isn't a number
+        server
+            .post("/4/strength")
+            .json(&json!([{"strength": 1, "speed": "NaN"}]))
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn csv_and_ndjson() {
+        let server = routes_test().await;
+        let csv = "name,strength,speed,candies\nDasher,5,8.5,3\nDancer,6,9.0,10\n";
+        server
+            .post("/4/strength")
+            .text(csv)
+            .content_type("text/csv")
+            .await
+            .assert_json(&11);
+        server
+            .post("/4/contest?categories=candies")
+            .text(csv)
+            .content_type("text/csv; charset=utf-8")
+            .await
+            .assert_json(&json!({"candies": {"value": 10, "names": ["Dancer"]}}));
+        server
+            .post("/4/stats")
+            .text("{\"strength\": 1}\n{\"strength\": 3}\n")
+            .content_type("application/x-ndjson")
+            .await
+            .assert_json_contains(&json!({"count": 2, "fields": {"strength": {"mean": 2.0}}}));
+
+        // NaN and out of range speeds make it through csv, but not through validation
+        for speed in ["NaN", "1e309", "-inf"] {
+            server
+                .post("/4/strength")
+                .text(format!("name,strength,speed\nDasher,5,{speed}\n"))
+                .content_type("text/csv")
+                .expect_failure()
+                .await
+                .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        server
+            .post("/4/strength")
+            .text("[{\"strength\": 1},\n{\"strength\": \"x\"}]")
+            .content_type("application/json")
+            .expect_failure()
+            .await
+            .assert_json_contains(&json!({"errors": [{"line": 2}]}));
+    }
+}
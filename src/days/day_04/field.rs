@@ -0,0 +1,175 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use serde::{de::IntoDeserializer, Deserialize};
+
+use super::Reindeer;
+use crate::error::AppError;
+
+/// One of the reindeer's fields, to compete, rank or summarise by.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Field {
+    Name,
+    Strength,
+    Speed,
+    Height,
+    AntlerWidth,
+    SnowMagicPower,
+    FavoriteFood,
+    Candies,
+}
+
+impl Field {
+    pub(super) const NUMERIC: [Field; 6] = [
+        Field::Strength,
+        Field::Speed,
+        Field::Height,
+        Field::AntlerWidth,
+        Field::SnowMagicPower,
+        Field::Candies,
+    ];
+
+    /// Never user input, so safe to put into a query.
+    pub(super) fn column(self) -> &'static str {
+        match self {
+            Field::Name => "name",
+            Field::Strength => "strength",
+            Field::Speed => "speed",
+            Field::Height => "height",
+            Field::AntlerWidth => "antler_width",
+            Field::SnowMagicPower => "snow_magic_power",
+            Field::FavoriteFood => "favorite_food",
+            Field::Candies => "candies",
+        }
+    }
+
+    pub(super) fn is_numeric(self) -> bool {
+        Field::NUMERIC.contains(&self)
+    }
+
+    pub(super) fn of(self, reindeer: &Reindeer) -> Value {
+        match self {
+            Field::Name => Value::Text(reindeer.name.clone()),
+            Field::Strength => Value::Int(reindeer.strength),
+            Field::Speed => Value::Float(reindeer.speed),
+            Field::Height => Value::Int(reindeer.height),
+            Field::AntlerWidth => Value::Int(reindeer.antler_width),
+            Field::SnowMagicPower => Value::Int(reindeer.snow_magic_power),
+            Field::FavoriteFood => Value::Text(reindeer.favorite_food.clone()),
+            Field::Candies => Value::Int(reindeer.candies),
+        }
+    }
+
+    /// A comma separated list like `speed,candies`, without duplicates.
+    pub(super) fn parse_list(list: &str) -> Result<Vec<Field>, AppError> {
+        let mut fields = list
+            .split(',')
+            .map(|name| {
+                Field::deserialize(name.trim().into_deserializer()).map_err(
+                    |_: serde::de::value::Error| {
+                        AppError::bad_request(format!("unknown field {name}"))
+                    },
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        fields.sort();
+        fields.dedup();
+        Ok(fields)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(untagged)]
+pub(super) enum Value {
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl Value {
+    pub(super) fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(v) => Some(*v as f64),
+            Value::Float(v) => Some(*v),
+            Value::Text(_) => None,
+        }
+    }
+
+    /// Values of one field always share a variant, text sorts alphabetically.
+    pub(super) fn compare(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::Text(a), Value::Text(b)) => a.cmp(b),
+            _ => self
+                .as_f64()
+                .unwrap_or(f64::NAN)
+                .total_cmp(&other.as_f64().unwrap_or(f64::NAN)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub(super) struct Winners {
+    /// The best value, `None` for an empty herd.
+    value: Option<Value>,
+    /// Everyone sharing it, in herd order.
+    names: Vec<String>,
+}
+
+/// The highest value of each field and every reindeer tied for it.
+pub(super) fn winners(herd: &[Reindeer], fields: &[Field]) -> BTreeMap<Field, Winners> {
+    fields
+        .iter()
+        .map(|&field| {
+            let best = herd.iter().map(|r| field.of(r)).max_by(|a, b| a.compare(b));
+            let names = herd
+                .iter()
+                .filter(|r| best.as_ref() == Some(&field.of(r)))
+                .map(|r| r.name.clone())
+                .collect();
+            (field, Winners { value: best, names })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{winners, Field, Value};
+    use crate::days::day_04::Reindeer;
+
+    fn reindeer(name: &str, speed: f64, food: &str) -> Reindeer {
+        serde_json::from_value(serde_json::json!({
+            "name": name, "strength": 1, "speed": speed, "favorite_food": food
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn ties_are_listed() {
+        let herd = [
+            reindeer("Dasher", 2.5, "hay"),
+            reindeer("Dancer", 3.0, "carrots"),
+            reindeer("Prancer", 3.0, "hay"),
+        ];
+        let won = winners(&herd, &[Field::Speed, Field::FavoriteFood, Field::Strength]);
+        assert_eq!(won[&Field::Speed].value, Some(Value::Float(3.0)));
+        assert_eq!(won[&Field::Speed].names, ["Dancer", "Prancer"]);
+        assert_eq!(won[&Field::FavoriteFood].names, ["Dasher", "Prancer"]);
+        assert_eq!(won[&Field::Strength].names.len(), 3);
+
+        let none = winners(&[], &[Field::Name]);
+        assert_eq!(none[&Field::Name].value, None);
+    }
+
+    #[test]
+    fn parse_list() {
+        assert_eq!(
+            Field::parse_list("candies, speed,candies").unwrap(),
+            [Field::Speed, Field::Candies]
+        );
+        assert!(Field::parse_list("speed,wings").is_err());
+        assert!(Field::parse_list("").is_err());
+    }
+}
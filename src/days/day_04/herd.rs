@@ -0,0 +1,178 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use sqlx::{Row, SqlitePool};
+
+use super::{contest, field::Field, field::Value, validate, Contest, ContestQuery, Reindeer};
+use crate::error::{AppError, JsonBody};
+
+const DEFAULT_PAGE: u32 = 10;
+const MAX_PAGE: u32 = 100;
+
+/// The whole herd in registration order, which is how contest ties are broken.
+pub(super) async fn load(pool: &SqlitePool) -> sqlx::Result<Vec<Reindeer>> {
+    sqlx::query_as::<_, Reindeer>("SELECT * FROM reindeer ORDER BY rowid")
+        .fetch_all(pool)
+        .await
+}
+
+pub(super) async fn list(State(pool): State<SqlitePool>) -> Result<Json<Vec<Reindeer>>, AppError> {
+    let mut herd = load(&pool).await?;
+    herd.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Json(herd))
+}
+
+pub(super) async fn get(
+    Path(name): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<Reindeer>, AppError> {
+    Ok(Json(
+        sqlx::query_as::<_, Reindeer>("SELECT * FROM reindeer WHERE name = ?")
+            .bind(name)
+            .fetch_one(&pool)
+            .await?,
+    ))
+}
+
+/// Registers the reindeer under `name`, or replaces its stats if it's known.
+pub(super) async fn put(
+    Path(name): Path<String>,
+    State(pool): State<SqlitePool>,
+    JsonBody(reindeer): JsonBody<Reindeer>,
+) -> Result<(StatusCode, Json<Reindeer>), AppError> {
+    if name.trim().is_empty() {
+        return Err(AppError::bad_request("reindeer need a name"));
+    }
+    let reindeer = Reindeer { name, ..reindeer };
+    validate(std::slice::from_ref(&reindeer))?;
+
+    let mut tx = pool.begin().await?;
+    let known = sqlx::query("SELECT 1 FROM reindeer WHERE name = ?")
+        .bind(&reindeer.name)
+        .fetch_optional(&mut *tx)
+        .await?
+        .is_some();
+    // an update keeps the rowid, and with it the reindeer's place in the herd
+    sqlx::query(
+        "INSERT INTO reindeer
+            (name, strength, speed, height, antler_width, snow_magic_power, favorite_food, candies)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (name) DO UPDATE SET
+            strength = excluded.strength,
+            speed = excluded.speed,
+            height = excluded.height,
+            antler_width = excluded.antler_width,
+            snow_magic_power = excluded.snow_magic_power,
+            favorite_food = excluded.favorite_food,
+            candies = excluded.candies",
+    )
+    .bind(&reindeer.name)
+    .bind(reindeer.strength)
+    .bind(reindeer.speed)
+    .bind(reindeer.height)
+    .bind(reindeer.antler_width)
+    .bind(reindeer.snow_magic_power)
+    .bind(&reindeer.favorite_food)
+    .bind(reindeer.candies)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    let status = if known {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+    Ok((status, Json(reindeer)))
+}
+
+pub(super) async fn delete(
+    Path(name): Path<String>,
+    State(pool): State<SqlitePool>,
+) -> Result<StatusCode, AppError> {
+    let deleted = sqlx::query("DELETE FROM reindeer WHERE name = ?")
+        .bind(&name)
+        .execute(&pool)
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(AppError::NotFound(format!("no reindeer named {name}")));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn stored_contest(
+    Query(q): Query<ContestQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<Contest>, AppError> {
+    contest(&load(&pool).await?, q).map(Json)
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct PageQuery {
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+#[derive(serde::Serialize)]
+pub(super) struct Leaderboard {
+    stat: Field,
+    /// Reindeer in the herd, for paging.
+    total: i64,
+    entries: Vec<Entry>,
+}
+
+#[derive(serde::Serialize)]
+struct Entry {
+    /// Tied reindeer share a rank, and the next rank is skipped (1, 1, 3).
+    rank: i64,
+    name: String,
+    value: Value,
+}
+
+pub(super) async fn leaderboard(
+    Path(stat): Path<Field>,
+    Query(q): Query<PageQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<Leaderboard>, AppError> {
+    if !stat.is_numeric() {
+        return Err(AppError::bad_request(format!(
+            "{} isn't a stat",
+            stat.column()
+        )));
+    }
+    let column = stat.column();
+    let limit = q.limit.unwrap_or(DEFAULT_PAGE).clamp(1, MAX_PAGE);
+
+    let entries = sqlx::query(&format!(
+        "SELECT name, {column} AS value, RANK() OVER (ORDER BY {column} DESC) AS rank
+        FROM reindeer
+        ORDER BY {column} DESC, name
+        LIMIT ? OFFSET ?"
+    ))
+    .bind(limit)
+    .bind(q.offset.unwrap_or(0))
+    .fetch_all(&pool)
+    .await?
+    .into_iter()
+    .map(|row| Entry {
+        rank: row.get("rank"),
+        name: row.get("name"),
+        value: match stat {
+            Field::Speed => Value::Float(row.get("value")),
+            _ => Value::Int(row.get("value")),
+        },
+    })
+    .collect();
+    let total = sqlx::query_scalar("SELECT COUNT(*) FROM reindeer")
+        .fetch_one(&pool)
+        .await?;
+
+    Ok(Json(Leaderboard {
+        stat,
+        total,
+        entries,
+    }))
+}
@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+use axum::{extract::State, Json};
+use sqlx::SqlitePool;
+
+use super::{field::Field, field::Value, herd, validate, Reindeer};
+use crate::{error::AppError, records::Records};
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub(super) struct Stats {
+    count: usize,
+    /// Every numeric field, empty for an empty herd.
+    fields: BTreeMap<Field, Summary>,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+struct Summary {
+    min: Value,
+    max: Value,
+    mean: f64,
+    median: f64,
+    /// Of the whole herd, not a sample of it.
+    stddev: f64,
+}
+
+pub(super) async fn stored(State(pool): State<SqlitePool>) -> Result<Json<Stats>, AppError> {
+    Ok(Json(stats(&herd::load(&pool).await?)))
+}
+
+pub(super) async fn ad_hoc(records: Records) -> Result<Json<Stats>, AppError> {
+    let payload: Vec<Reindeer> = records.collect().await?;
+    validate(&payload)?;
+    Ok(Json(stats(&payload)))
+}
+
+fn stats(herd: &[Reindeer]) -> Stats {
+    let fields = if herd.is_empty() {
+        BTreeMap::new()
+    } else {
+        Field::NUMERIC
+            .into_iter()
+            .map(|field| (field, summary(herd, field)))
+            .collect()
+    };
+    Stats {
+        count: herd.len(),
+        fields,
+    }
+}
+
+/// `herd` isn't empty and `field` is numeric with validated, finite values.
+fn summary(herd: &[Reindeer], field: Field) -> Summary {
+    let mut values: Vec<Value> = herd.iter().map(|r| field.of(r)).collect();
+    values.sort_by(|a, b| a.compare(b));
+    let numbers: Vec<f64> = values.iter().filter_map(Value::as_f64).collect();
+
+    let n = numbers.len() as f64;
+    let mean = numbers.iter().sum::<f64>() / n;
+    let middle = numbers.len() / 2;
+    let median = if numbers.len().is_multiple_of(2) {
+        (numbers[middle - 1] + numbers[middle]) / 2.0
+    } else {
+        numbers[middle]
+    };
+    let variance = numbers.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+
+    Summary {
+        min: values[0].clone(),
+        max: values[values.len() - 1].clone(),
+        mean,
+        median,
+        stddev: variance.sqrt(),
+    }
+}
@@ -2,6 +2,8 @@ use axum::extract::Query;
 use axum::routing::post;
 use axum::{Json, Router};
 
+use crate::error::{AppError, JsonBody};
+
 pub(super) fn route() -> Router {
     Router::new().route("/", post(day))
 }
@@ -17,15 +19,47 @@ struct Pagination {
 #[tracing::instrument(ret)]
 async fn day(
     Query(q): Query<Pagination>,
-    Json(payload): Json<Vec<String>>,
-) -> Json<Vec<serde_json::Value>> {
+    JsonBody(payload): JsonBody<Vec<String>>,
+) -> Result<Json<Vec<serde_json::Value>>, AppError> {
+    if q.split == Some(0) {
+        return Err(AppError::bad_request("split must be greater than 0"));
+    }
     let limit = q.limit.unwrap_or(payload.len());
     let mut iter = payload.into_iter().skip(q.offset).take(limit);
 
-    Json(match q.split {
+    Ok(Json(match q.split {
         Some(split) => (0..limit.div_ceil(split))
             .map(|_| iter.by_ref().take(split).collect())
             .collect(),
-        None => iter.map(|x| serde_json::to_value(x).unwrap()).collect(),
-    })
+        None => iter.map(serde_json::Value::String).collect(),
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::days::routes_test;
+
+    #[tokio::test]
+    async fn task1() {
+        routes_test()
+            .await
+            .post("/5?offset=1&limit=2&split=1")
+            .json(&json!(["Ava", "Caspian", "Eliza", "Felix"]))
+            .await
+            .assert_json(&json!([["Caspian"], ["Eliza"]]));
+    }
+
+    #[tokio::test]
+    async fn malformed() {
+        let res = routes_test()
+            .await
+            .post("/5?split=0")
+            .json(&json!(["Ava"]))
+            .expect_failure()
+            .await;
+        res.assert_status_bad_request();
+        res.assert_json_contains(&json!({"status": 400}));
+    }
 }
@@ -19,10 +19,11 @@ async fn elf_count(payload: String) -> impl IntoResponse {
 
     let (mut elf_on_a_shelf, mut shelf_with_no_elf_on_it) = (0, 0);
 
-    for i in 0..=payload.len() - SHELF.len() {
-        if &payload[i..i + SHELF.len()] == SHELF {
+    let bytes = payload.as_bytes();
+    for (i, window) in bytes.windows(SHELF.len()).enumerate() {
+        if window == SHELF.as_bytes() {
             if i >= ELF_ON_A_SHELF.len()
-                && &payload[(i - ELF_ON_A_SHELF.len())..i] == ELF_ON_A_SHELF
+                && &bytes[(i - ELF_ON_A_SHELF.len())..i] == ELF_ON_A_SHELF.as_bytes()
             {
                 elf_on_a_shelf += 1;
             } else {
@@ -71,6 +72,21 @@ Look, there is also an elf on that shelf!",
             .assert_json(&json!({"elf":5,"elf on a shelf":1,"shelf with no elf on it":1}));
     }
 
+    #[tokio::test]
+    async fn short_and_unicode() {
+        let server = routes_test().await;
+        server
+            .post("/6")
+            .text("elf")
+            .await
+            .assert_json(&json!({"elf":1,"elf on a shelf":0,"shelf with no elf on it":0}));
+        server
+            .post("/6")
+            .text("🎄 elf on a shelf 🎁")
+            .await
+            .assert_json(&json!({"elf":2,"elf on a shelf":1,"shelf with no elf on it":0}));
+    }
+
     #[tokio::test]
     async fn extra1() {
         routes_test()
@@ -1,31 +1,55 @@
 use std::collections::HashMap;
 
-use axum::{routing::get, Json, Router};
-use axum_extra::extract::CookieJar;
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_extra::extract::cookie::Cookie;
 use base64::prelude::*;
 
+use crate::error::AppError;
+
+mod explain;
+mod jar;
+mod plan;
+
 pub fn route() -> Router {
+    route_with(jar::Mode::from_env())
+}
+
+fn route_with(mode: jar::Mode) -> Router {
     Router::new()
         .route("/decode", get(decode))
         .route("/bake", get(get_cookie))
+        .route("/bake/plan", post(plan::plan))
+        .route("/bake/explain", get(explain::explain))
+        .with_state(mode)
 }
 
-fn decode_cookie<T: serde::de::DeserializeOwned>(encrypt: &str) -> Option<T> {
-    BASE64_STANDARD
-        .decode(encrypt)
-        .map(|x| serde_json::from_slice(&x).unwrap())
-        .ok()
+fn decode_cookie<T: serde::de::DeserializeOwned>(jar: &jar::Jar) -> Result<T, AppError> {
+    let cookie = jar
+        .get("recipe")
+        .ok_or_else(|| AppError::bad_request("missing or invalid recipe cookie"))?;
+    Ok(serde_json::from_slice(&BASE64_STANDARD.decode(cookie)?)?)
+}
+
+fn encode_cookie<T: serde::Serialize>(value: &T) -> Result<Cookie<'static>, AppError> {
+    let value = BASE64_STANDARD.encode(serde_json::to_vec(value)?);
+    Ok(Cookie::build(("recipe", value)).path("/7").build())
 }
 
 type Recipe = HashMap<String, usize>;
 
-async fn decode(jar: CookieJar) -> Json<serde_json::Value> {
-    decode_cookie(jar.get("recipe").unwrap().value())
-        .map(Json)
-        .unwrap()
+async fn decode(
+    State(mode): State<jar::Mode>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    decode_cookie(&mode.jar(&headers)).map(Json)
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 struct BakingInput {
     recipe: Recipe,
     pantry: Recipe,
@@ -37,38 +61,236 @@ struct BakingResult {
     pantry: Recipe,
 }
 
-async fn get_cookie(jar: CookieJar) -> Json<BakingResult> {
-    decode_cookie(jar.get("recipe").unwrap().value())
-        .map(|x: BakingInput| {
-            let mut cookies = usize::MAX;
-
-            for (ingredient, recipe_amount) in x.recipe.iter() {
-                if recipe_amount == &0 {
-                    continue;
-                }
-                if let Some(pantry_amount) = x.pantry.get(ingredient) {
-                    if pantry_amount < recipe_amount {
-                        return Json(BakingResult {
-                            cookies: 0,
-                            pantry: x.pantry,
-                        });
-                    }
+/// Also sets the cookie to the recipe and what's left of the pantry, so the
+/// next request keeps baking from there.
+async fn get_cookie(
+    State(mode): State<jar::Mode>,
+    headers: HeaderMap,
+) -> Result<(jar::Jar, Json<BakingResult>), AppError> {
+    let jar = mode.jar(&headers);
+    let input: BakingInput = decode_cookie(&jar)?;
+    let recipe = input.recipe.clone();
+    let result = bake(input);
+    let cookie = encode_cookie(&BakingInput {
+        recipe,
+        pantry: result.pantry.clone(),
+    })?;
+    Ok((jar.add(cookie), Json(result)))
+}
 
-                    cookies = cookies.min(pantry_amount / recipe_amount);
-                }
-            }
+/// How many cookies `pantry` holds enough of ingredient for. Ingredients the
+/// pantry doesn't list are out of stock, ones the recipe needs none of don't
+/// limit anything.
+fn batches(recipe_amount: usize, pantry_amount: Option<usize>) -> Option<usize> {
+    (recipe_amount > 0).then(|| pantry_amount.unwrap_or(0) / recipe_amount)
+}
 
-            let mut updated_pantry = x.pantry;
-            for (ingredient, recipe_amount) in x.recipe.iter() {
-                if let Some(pantry_amount) = updated_pantry.get_mut(ingredient) {
-                    *pantry_amount = pantry_amount.saturating_sub(cookies * recipe_amount);
-                }
+fn bake(x: BakingInput) -> BakingResult {
+    // a recipe of nothing can't be baked, rather than baked endlessly
+    let cookies = x
+        .recipe
+        .iter()
+        .filter_map(|(ingredient, amount)| batches(*amount, x.pantry.get(ingredient).copied()))
+        .min()
+        .unwrap_or(0);
+
+    let mut updated_pantry = x.pantry;
+    for (ingredient, recipe_amount) in x.recipe.iter() {
+        if let Some(pantry_amount) = updated_pantry.get_mut(ingredient) {
+            *pantry_amount -= cookies * recipe_amount;
+        }
+    }
+
+    BakingResult {
+        cookies,
+        pantry: updated_pantry,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{response::IntoResponse, Router};
+    use axum_extra::extract::cookie::Key;
+    use axum_test::TestServer;
+    use serde_json::json;
+
+    use super::{encode_cookie, jar::Mode};
+    use crate::days::routes_test;
+
+    /// A `Cookie` header value as the server would have set it in `mode`.
+    fn mint(mode: &Mode, value: &serde_json::Value) -> String {
+        let jar = mode.jar(&Default::default()).add(encode_cookie(value).unwrap());
+        let res = (jar, ()).into_response();
+        let set_cookie = res.headers()["set-cookie"].to_str().unwrap();
+        set_cookie.split(';').next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn task1() {
+        routes_test()
+            .await
+            .get("/7/decode")
+            .add_header(
+                "Cookie",
+                "recipe=eyJmbG91ciI6MTAwLCJjaG9jb2xhdGUgY2hpcHMiOjIwfQ==",
+            )
+            .await
+            .assert_json(&json!({"flour":100,"chocolate chips":20}));
+    }
+
+    #[tokio::test]
+    async fn malformed() {
+        let server = routes_test().await;
+        server
+            .get("/7/decode")
+            .expect_failure()
+            .await
+            .assert_status_bad_request();
+        for cookie in ["recipe=not*base64", "recipe=bm90IGpzb24="] {
+            let res = server
+                .get("/7/bake")
+                .add_header("Cookie", cookie)
+                .expect_failure()
+                .await;
+            res.assert_status_bad_request();
+            res.assert_json_contains(&json!({"status": 400}));
+        }
+    }
+
+    #[tokio::test]
+    async fn bake_sets_pantry() {
+        let server = routes_test().await;
+        let input = json!({"recipe": {"flour": 10}, "pantry": {"flour": 25}});
+        let res = server
+            .get("/7/bake")
+            .add_header("Cookie", mint(&Mode::Plain, &input))
+            .await;
+        res.assert_json(&json!({"cookies": 2, "pantry": {"flour": 5}}));
+        assert!(res.header("set-cookie").to_str().unwrap().contains("Path=/7"));
+
+        // the saved cookie carries the leftovers
+        server
+            .get("/7/bake")
+            .await
+            .assert_json(&json!({"cookies": 0, "pantry": {"flour": 5}}));
+    }
+
+    #[tokio::test]
+    async fn keyed_modes() {
+        let input = json!({"recipe": {"flour": 10}, "pantry": {"flour": 25}});
+        let forged = mint(&Mode::Plain, &input);
+        let key = Key::generate();
+
+        for mode in [Mode::Signed(key.clone()), Mode::Private(key.clone())] {
+            let server = TestServer::new(Router::new().nest("/7", super::route_with(mode.clone())))
+                .unwrap();
+            server
+                .get("/7/bake")
+                .add_header("Cookie", mint(&mode, &input))
+                .await
+                .assert_json(&json!({"cookies": 2, "pantry": {"flour": 5}}));
+            server
+                .get("/7/bake")
+                .add_header("Cookie", forged.clone())
+                .await
+                .assert_status_bad_request();
+            // signed with another key
+            let other = match mode {
+                Mode::Signed(_) => Mode::Signed(Key::generate()),
+                _ => Mode::Private(Key::generate()),
+            };
+            server
+                .get("/7/decode")
+                .add_header("Cookie", mint(&other, &input))
+                .await
+                .assert_status_bad_request();
+        }
+    }
+
+    #[tokio::test]
+    async fn plan() {
+        let server = routes_test().await;
+        let res = server
+            .post("/7/bake/plan")
+            .json(&json!({
+                "recipes": {
+                    "cake": {"ingredients": {"flour": 6, "egg": 1}, "value": 5},
+                    "cookie": {"ingredients": {"flour": 5}, "value": 4, "min": 1}
+                },
+                "pantry": {"flour": 16, "egg": 2}
+            }))
+            .await;
+        res.assert_json(&json!({
+            "batches": {"cake": 1, "cookie": 2},
+            "value": 13,
+            "exact": true,
+            "pantry": {"flour": 0, "egg": 1},
+            "bottlenecks": {
+                "egg": {"available": 2, "used": 1, "left": 1, "limiting": false},
+                "flour": {"available": 16, "used": 16, "left": 0, "limiting": true}
             }
+        }));
+        server
+            .post("/7/bake/plan")
+            .json(&json!({"recipes": {}, "pantry": "nope"}))
+            .expect_failure()
+            .await
+            .assert_status(axum::http::StatusCode::UNPROCESSABLE_ENTITY);
+    }
 
-            Json(BakingResult {
-                cookies,
-                pantry: updated_pantry,
-            })
-        })
-        .unwrap()
+    #[tokio::test]
+    async fn missing_ingredients() {
+        let server = routes_test().await;
+        let input = json!({
+            "recipe": {"flour": 10, "chocolate chips": 5, "love": 0},
+            "pantry": {"flour": 25}
+        });
+        server
+            .get("/7/bake")
+            .add_header("Cookie", mint(&Mode::Plain, &input))
+            .await
+            .assert_json(&json!({"cookies": 0, "pantry": {"flour": 25}}));
+
+        let input = json!({"recipe": {"love": 0}, "pantry": {"flour": 25}});
+        server
+            .get("/7/bake")
+            .add_header("Cookie", mint(&Mode::Plain, &input))
+            .await
+            .assert_json(&json!({"cookies": 0, "pantry": {"flour": 25}}));
+    }
+
+    #[tokio::test]
+    async fn explain() {
+        let server = routes_test().await;
+        let input = json!({
+            "recipe": {"flour": 10, "sugar": 2, "chocolate chips": 5},
+            "pantry": {"flour": 25, "sugar": 100}
+        });
+        let cookie = mint(&Mode::Plain, &input);
+        server
+            .get("/7/bake/explain?target=3")
+            .add_header("Cookie", cookie.clone())
+            .await
+            .assert_json(&json!({
+                "cookies": 0,
+                "target": 3,
+                "ingredients": {
+                    "chocolate chips": {
+                        "required": 5, "available": 0, "allows": 0, "limiting": true, "to_buy": 15
+                    },
+                    "flour": {
+                        "required": 10, "available": 25, "allows": 2, "limiting": false, "to_buy": 5
+                    },
+                    "sugar": {
+                        "required": 2, "available": 100, "allows": 50, "limiting": false, "to_buy": 0
+                    }
+                },
+                "shopping_list": {"chocolate chips": 15, "flour": 5}
+            }));
+        server
+            .get("/7/bake/explain")
+            .add_header("Cookie", cookie)
+            .await
+            .assert_json_contains(&json!({"target": 1, "shopping_list": {"chocolate chips": 5}}));
+    }
 }
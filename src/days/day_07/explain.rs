@@ -0,0 +1,79 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    Json,
+};
+
+use super::{batches, bake, decode_cookie, jar, BakingInput};
+use crate::error::AppError;
+
+#[derive(serde::Deserialize)]
+pub(super) struct ExplainQuery {
+    /// Cookies to plan the shopping for, one more than the pantry allows by default.
+    target: Option<usize>,
+}
+
+#[derive(serde::Serialize)]
+pub(super) struct Explanation {
+    cookies: usize,
+    target: usize,
+    ingredients: BTreeMap<String, Report>,
+    /// What to buy to bake `target` cookies, only the ingredients short.
+    shopping_list: BTreeMap<String, usize>,
+}
+
+#[derive(serde::Serialize)]
+struct Report {
+    /// Per cookie.
+    required: usize,
+    available: usize,
+    /// Cookies this ingredient alone is enough for, `None` if it isn't needed.
+    allows: Option<usize>,
+    /// Whether it's what stops the baker from baking more.
+    limiting: bool,
+    to_buy: usize,
+}
+
+/// Why `/bake` would bake what it does from the same cookie, without baking.
+pub(super) async fn explain(
+    State(mode): State<jar::Mode>,
+    headers: HeaderMap,
+    Query(q): Query<ExplainQuery>,
+) -> Result<Json<Explanation>, AppError> {
+    let input: BakingInput = decode_cookie(&mode.jar(&headers))?;
+    let cookies = bake(input.clone()).cookies;
+    let target = q.target.unwrap_or(cookies.saturating_add(1));
+
+    let mut ingredients = BTreeMap::new();
+    let mut shopping_list = BTreeMap::new();
+    for (ingredient, &required) in &input.recipe {
+        let available = input.pantry.get(ingredient).copied().unwrap_or(0);
+        let needed = required
+            .checked_mul(target)
+            .ok_or_else(|| AppError::bad_request("target is too large"))?;
+        let to_buy = needed.saturating_sub(available);
+        if to_buy > 0 {
+            shopping_list.insert(ingredient.clone(), to_buy);
+        }
+        let allows = batches(required, input.pantry.get(ingredient).copied());
+        ingredients.insert(
+            ingredient.clone(),
+            Report {
+                required,
+                available,
+                allows,
+                limiting: allows == Some(cookies),
+                to_buy,
+            },
+        );
+    }
+
+    Ok(Json(Explanation {
+        cookies,
+        target,
+        ingredients,
+        shopping_list,
+    }))
+}
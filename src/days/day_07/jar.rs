@@ -0,0 +1,91 @@
+use axum::{
+    http::HeaderMap,
+    response::{IntoResponseParts, ResponseParts},
+};
+use axum_extra::extract::{
+    cookie::{Cookie, Key},
+    CookieJar, PrivateCookieJar, SignedCookieJar,
+};
+use base64::prelude::*;
+
+/// How the recipe cookie is protected.
+#[derive(Clone)]
+pub(super) enum Mode {
+    /// Base64 json anyone can read and forge, what the challenge sends.
+    Plain,
+    /// Readable, but rejected if it wasn't issued with the key.
+    Signed(Key),
+    /// Encrypted and authenticated with the key.
+    Private(Key),
+}
+
+impl Mode {
+    /// Picks the mode from the environment:
+    ///
+    /// - `DAY7_COOKIE_KEY`: base64 of at least 64 random bytes, plain mode without one
+    /// - `DAY7_COOKIE_MODE`: `signed` (default with a key) or `private`
+    pub(super) fn from_env() -> Self {
+        let Ok(key) = std::env::var("DAY7_COOKIE_KEY") else {
+            return Mode::Plain;
+        };
+        // a typo here must not quietly downgrade to forgeable cookies
+        let key = BASE64_STANDARD
+            .decode(key.trim())
+            .ok()
+            .and_then(|bytes| Key::try_from(bytes.as_slice()).ok())
+            .expect("DAY7_COOKIE_KEY must be base64 of at least 64 bytes");
+
+        match std::env::var("DAY7_COOKIE_MODE").as_deref() {
+            Ok("private") => Mode::Private(key),
+            Ok("signed") | Err(_) => Mode::Signed(key),
+            Ok(other) => panic!("unknown DAY7_COOKIE_MODE {other}"),
+        }
+    }
+
+    pub(super) fn jar(&self, headers: &HeaderMap) -> Jar {
+        match self {
+            Mode::Plain => Jar::Plain(CookieJar::from_headers(headers)),
+            Mode::Signed(key) => Jar::Signed(SignedCookieJar::from_headers(headers, key.clone())),
+            Mode::Private(key) => {
+                Jar::Private(PrivateCookieJar::from_headers(headers, key.clone()))
+            }
+        }
+    }
+}
+
+pub(super) enum Jar {
+    Plain(CookieJar),
+    Signed(SignedCookieJar),
+    Private(PrivateCookieJar),
+}
+
+impl Jar {
+    /// The cookie's value, `None` if it's missing or fails verification.
+    pub(super) fn get(&self, name: &str) -> Option<String> {
+        match self {
+            Jar::Plain(jar) => jar.get(name).map(|c| c.value().to_string()),
+            Jar::Signed(jar) => jar.get(name).map(|c| c.value().to_string()),
+            Jar::Private(jar) => jar.get(name).map(|c| c.value().to_string()),
+        }
+    }
+
+    pub(super) fn add(self, cookie: Cookie<'static>) -> Self {
+        match self {
+            Jar::Plain(jar) => Jar::Plain(jar.add(cookie)),
+            Jar::Signed(jar) => Jar::Signed(jar.add(cookie)),
+            Jar::Private(jar) => Jar::Private(jar.add(cookie)),
+        }
+    }
+}
+
+impl IntoResponseParts for Jar {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        match self {
+            Jar::Plain(jar) => jar.into_response_parts(res),
+            Jar::Signed(jar) => jar.into_response_parts(res),
+            Jar::Private(jar) => jar.into_response_parts(res),
+        }
+    }
+}
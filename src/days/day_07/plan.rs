@@ -0,0 +1,362 @@
+use std::collections::{BTreeMap, HashMap};
+
+use axum::Json;
+
+use super::Recipe;
+use crate::error::{AppError, JsonBody};
+
+/// Largest number of batch combinations searched exhaustively.
+const EXACT_LIMIT: u128 = 200_000;
+const MAX_RECIPES: usize = 100;
+const MAX_INGREDIENTS: usize = 1_000;
+
+#[derive(serde::Deserialize)]
+pub(super) struct PlanInput {
+    recipes: BTreeMap<String, NamedRecipe>,
+    pantry: Recipe,
+}
+
+#[derive(serde::Deserialize)]
+struct NamedRecipe {
+    ingredients: Recipe,
+    /// What one batch is worth, batches count equally by default.
+    #[serde(default = "one")]
+    value: u64,
+    /// Batches that have to be baked whatever the value.
+    #[serde(default)]
+    min: usize,
+}
+
+fn one() -> u64 {
+    1
+}
+
+#[derive(serde::Serialize, Debug)]
+pub(super) struct Plan {
+    batches: BTreeMap<String, usize>,
+    value: u64,
+    /// Whether the plan is proven optimal or came from the greedy fallback.
+    exact: bool,
+    pantry: Recipe,
+    bottlenecks: BTreeMap<String, Bottleneck>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+struct Bottleneck {
+    available: usize,
+    used: usize,
+    left: usize,
+    /// Too little is left for one more batch of any recipe using it.
+    limiting: bool,
+}
+
+/// A recipe reduced to what the solvers need, ingredients by index.
+struct Item {
+    /// Only the ingredients used, as (index, quantity) pairs.
+    needs: Vec<(usize, usize)>,
+    value: u64,
+    min: usize,
+}
+
+impl Item {
+    /// Batches `stock` allows, never unbounded since empty recipes are refused.
+    fn fits(&self, stock: &[usize]) -> usize {
+        self.needs
+            .iter()
+            .map(|(i, need)| stock[*i] / need)
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn take(&self, stock: &mut [usize], batches: usize) {
+        for (i, need) in &self.needs {
+            stock[*i] -= need * batches;
+        }
+    }
+
+    fn give_back(&self, stock: &mut [usize], batches: usize) {
+        for (i, need) in &self.needs {
+            stock[*i] += need * batches;
+        }
+    }
+}
+
+/// Solved on the blocking pool, the exact search can take a while.
+pub(super) async fn plan(JsonBody(input): JsonBody<PlanInput>) -> Result<Json<Plan>, AppError> {
+    tokio::task::spawn_blocking(move || solve(input))
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .map(Json)
+}
+
+fn solve(input: PlanInput) -> Result<Plan, AppError> {
+    if input.recipes.len() > MAX_RECIPES {
+        return Err(AppError::bad_request(format!(
+            "at most {MAX_RECIPES} recipes can be planned"
+        )));
+    }
+    let mut ingredients: Vec<&String> = input
+        .recipes
+        .values()
+        .flat_map(|r| r.ingredients.keys())
+        .chain(input.pantry.keys())
+        .collect();
+    ingredients.sort();
+    ingredients.dedup();
+    if ingredients.len() > MAX_INGREDIENTS {
+        return Err(AppError::bad_request(format!(
+            "at most {MAX_INGREDIENTS} ingredients can be planned"
+        )));
+    }
+
+    let mut items = Vec::new();
+    for (name, recipe) in &input.recipes {
+        let needs: Vec<(usize, usize)> = recipe
+            .ingredients
+            .iter()
+            .filter(|(_, need)| **need > 0)
+            .map(|(ingredient, need)| (ingredients.binary_search(&ingredient).unwrap(), *need))
+            .collect();
+        if needs.is_empty() {
+            return Err(AppError::bad_request(format!(
+                "recipe {name} needs no ingredients"
+            )));
+        }
+        items.push(Item {
+            needs,
+            value: recipe.value,
+            min: recipe.min,
+        });
+    }
+
+    // ingredients missing from the pantry are simply out of stock
+    let available: Vec<usize> = ingredients
+        .iter()
+        .map(|i| input.pantry.get(*i).copied().unwrap_or(0))
+        .collect();
+    let mut stock = available.clone();
+    for item in &items {
+        if item.fits(&stock) < item.min {
+            return Err(AppError::bad_request(
+                "the pantry can't cover the minimum batches",
+            ));
+        }
+        item.take(&mut stock, item.min);
+    }
+
+    let space = items.iter().fold(1u128, |space, item| {
+        space.saturating_mul(item.fits(&stock) as u128 + 1)
+    });
+    let exact = space <= EXACT_LIMIT;
+    let extra = if exact {
+        exact_extra(&items, &stock)
+    } else {
+        greedy_extra(&items, &stock)
+    };
+
+    let mut batches = BTreeMap::new();
+    let mut value = 0u64;
+    for ((name, item), extra) in input.recipes.keys().zip(&items).zip(extra) {
+        item.take(&mut stock, extra);
+        let count = item.min + extra;
+        value = value.saturating_add(item.value.saturating_mul(count as u64));
+        batches.insert(name.clone(), count);
+    }
+
+    let mut smallest_needs = vec![None; ingredients.len()];
+    for (i, need) in items.iter().flat_map(|item| &item.needs) {
+        let smallest: &mut Option<usize> = &mut smallest_needs[*i];
+        *smallest = Some(smallest.map_or(*need, |s| s.min(*need)));
+    }
+    let bottlenecks = ingredients
+        .iter()
+        .zip(smallest_needs)
+        .enumerate()
+        .map(|(i, (name, smallest_need))| {
+            let bottleneck = Bottleneck {
+                available: available[i],
+                used: available[i] - stock[i],
+                left: stock[i],
+                limiting: smallest_need.is_some_and(|need| stock[i] < need),
+            };
+            (name.to_string(), bottleneck)
+        })
+        .collect();
+    let pantry = ingredients
+        .iter()
+        .zip(&stock)
+        .filter(|(name, _)| input.pantry.contains_key(**name))
+        .map(|(name, left)| (name.to_string(), *left))
+        .collect::<HashMap<_, _>>();
+
+    Ok(Plan {
+        batches,
+        value,
+        exact,
+        pantry,
+        bottlenecks,
+    })
+}
+
+/// Tries every combination of batches, most batches first, keeping the most
+/// valuable. Depth first without recursion, the stack holds the batches of
+/// each item so far and the value before them.
+fn exact_extra(items: &[Item], stock: &[usize]) -> Vec<usize> {
+    let mut stock = stock.to_vec();
+    let mut stack: Vec<(usize, u64)> = Vec::with_capacity(items.len());
+    let mut value = 0u64;
+    let mut best: Option<(u64, Vec<usize>)> = None;
+    loop {
+        if let Some(item) = items.get(stack.len()) {
+            let batches = item.fits(&stock);
+            item.take(&mut stock, batches);
+            stack.push((batches, value));
+            value = value.saturating_add(item.value.saturating_mul(batches as u64));
+            continue;
+        }
+        if best.as_ref().is_none_or(|(most, _)| value > *most) {
+            best = Some((value, stack.iter().map(|(batches, _)| *batches).collect()));
+        }
+
+        // next is one batch less of the last item that has any left
+        loop {
+            let Some((batches, before)) = stack.pop() else {
+                return best.map(|(_, extra)| extra).unwrap_or_default();
+            };
+            if batches > 0 {
+                let item = &items[stack.len()];
+                item.give_back(&mut stock, 1);
+                stack.push((batches - 1, before));
+                value = before.saturating_add(item.value.saturating_mul(batches as u64 - 1));
+                break;
+            }
+        }
+    }
+}
+
+/// Repeatedly bakes the recipe with the best value for what it uses up,
+/// weighing each ingredient by how scarce it has become.
+fn greedy_extra(items: &[Item], stock: &[usize]) -> Vec<usize> {
+    let mut stock = stock.to_vec();
+    let mut extra = vec![0; items.len()];
+    loop {
+        let best = items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.value > 0 && item.fits(&stock) > 0)
+            .map(|(i, item)| {
+                let cost: f64 = item
+                    .needs
+                    .iter()
+                    .map(|(i, need)| *need as f64 / stock[*i] as f64)
+                    .sum();
+                (i, item.value as f64 / cost)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        let Some((i, _)) = best else {
+            return extra;
+        };
+        // half of what fits, so the others get reconsidered as stock shrinks
+        let batches = items[i].fits(&stock).div_ceil(2);
+        items[i].take(&mut stock, batches);
+        extra[i] += batches;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{solve, Bottleneck, PlanInput};
+
+    fn input(value: serde_json::Value) -> PlanInput {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn exact_beats_greedy_choice() {
+        // one cake is worth more than a cookie, but two cookies beat it
+        let plan = solve(input(json!({
+            "recipes": {
+                "cake": {"ingredients": {"flour": 6}, "value": 5},
+                "cookie": {"ingredients": {"flour": 5}, "value": 4}
+            },
+            "pantry": {"flour": 10, "sugar": 3}
+        })))
+        .unwrap();
+        assert!(plan.exact);
+        assert_eq!(plan.value, 8);
+        assert_eq!(plan.batches["cookie"], 2);
+        assert_eq!(plan.pantry["flour"], 0);
+        assert_eq!(plan.pantry["sugar"], 3);
+        assert_eq!(
+            plan.bottlenecks["flour"],
+            Bottleneck {
+                available: 10,
+                used: 10,
+                left: 0,
+                limiting: true
+            }
+        );
+        assert!(!plan.bottlenecks["sugar"].limiting);
+    }
+
+    #[test]
+    fn minimums_and_missing_ingredients() {
+        let plan = solve(input(json!({
+            "recipes": {
+                "cake": {"ingredients": {"flour": 6}, "value": 5, "min": 1},
+                "cookie": {"ingredients": {"flour": 5}, "value": 4},
+                "eclair": {"ingredients": {"cream": 1}, "value": 100}
+            },
+            "pantry": {"flour": 10}
+        })))
+        .unwrap();
+        assert_eq!(plan.batches["cake"], 1);
+        assert_eq!(plan.batches["cookie"], 0);
+        assert_eq!(plan.batches["eclair"], 0);
+        assert!(plan.bottlenecks["cream"].limiting);
+
+        assert!(solve(input(json!({
+            "recipes": {"cake": {"ingredients": {"flour": 6}, "min": 2}},
+            "pantry": {"flour": 10}
+        })))
+        .is_err());
+        assert!(solve(input(json!({
+            "recipes": {"air": {"ingredients": {"flour": 0}}},
+            "pantry": {"flour": 10}
+        })))
+        .is_err());
+    }
+
+    #[test]
+    fn large_inputs_fall_back_to_greedy() {
+        let plan = solve(input(json!({
+            "recipes": {
+                "cake": {"ingredients": {"flour": 3, "egg": 2}, "value": 7},
+                "cookie": {"ingredients": {"flour": 1}, "value": 2},
+                "meringue": {"ingredients": {"egg": 1}, "value": 3}
+            },
+            "pantry": {"flour": 1_000_000, "egg": 1_000_000}
+        })))
+        .unwrap();
+        assert!(!plan.exact);
+        // flour goes to cookies and eggs to meringues, cakes use both
+        assert_eq!(plan.batches["cake"], 0);
+        assert_eq!(plan.value, 5_000_000);
+        assert_eq!(plan.pantry["flour"], 0);
+        assert_eq!(plan.pantry["egg"], 0);
+    }
+
+    #[test]
+    fn too_many_recipes() {
+        let recipes: serde_json::Map<_, _> = (0..=super::MAX_RECIPES)
+            .map(|i| (format!("r{i}"), json!({"ingredients": {"flour": 1}})))
+            .collect();
+        assert!(solve(input(json!({"recipes": recipes, "pantry": {}}))).is_err());
+        let pantry: serde_json::Map<_, _> = (0..=super::MAX_INGREDIENTS)
+            .map(|i| (format!("i{i}"), json!(1)))
+            .collect();
+        assert!(solve(input(json!({"recipes": {}, "pantry": pantry}))).is_err());
+    }
+}
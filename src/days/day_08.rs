@@ -1,41 +1,102 @@
+use std::sync::Arc;
+
 use axum::{
-    extract::Path,
+    extract::{Path, State},
     routing::get,
     Json, Router,
 };
-use serde_json::{json, Value};
+
+use crate::error::AppError;
+
+mod source;
+
+use source::PokemonSource;
 
 pub(super) fn route() -> Router {
+    route_with(source::from_env())
+}
+
+fn route_with(source: Arc<dyn PokemonSource>) -> Router {
     Router::new()
         .route("/weight/:id", get(get_weight))
         .route("/drop/:id", get(get_momentum))
+        .with_state(source)
 }
 
-const API: &'static str = "https://pokeapi.co/api/v2/pokemon";
+const API: &str = "https://pokeapi.co/api/v2/pokemon";
 const G: f64 = 9.825;
 
-async fn get_pokemon_stat(id: u32) -> Value {
-    let url = format!("{}/{}", API, id);
+async fn get_pokemon_weight(source: &dyn PokemonSource, id: u32) -> Result<f64, AppError> {
+    source.pokemon(id).await.map(|pokemon| pokemon.weight / 10.0)
+}
 
-    match reqwest::get(&url).await {
-        Ok(response) if response.status().is_success() => {
-            response.json::<Value>().await.unwrap_or_else(|_| json!({}))
-        }
-        _ => json!({}),
-    }
+async fn get_weight(
+    State(source): State<Arc<dyn PokemonSource>>,
+    Path(id): Path<u32>,
+) -> Result<Json<f64>, AppError> {
+    get_pokemon_weight(source.as_ref(), id).await.map(Json)
 }
-async fn get_weight(Path(id): Path<u32>) -> Json<f64> {
-    get_pokemon_stat(id)
+
+async fn get_momentum(
+    State(source): State<Arc<dyn PokemonSource>>,
+    Path(id): Path<u32>,
+) -> Result<Json<f64>, AppError> {
+    get_pokemon_weight(source.as_ref(), id)
         .await
-        .get("weight")
-        .map(|weight| Json(weight.as_f64().unwrap_or(0.0) / 10.0))
-        .unwrap()
+        .map(|weight| Json(weight * (2.0 * G * 10.0).sqrt()))
 }
 
-async fn get_momentum(Path(id): Path<u32>) -> Json<f64> {
-    get_pokemon_stat(id)
-        .await
-        .get("weight")
-        .map(|weight| Json(weight.as_f64().unwrap_or(0.0) / 10.0 * (2.0 * G * 10.0).sqrt()))
-        .unwrap()
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+
+    use super::source::{Fixtures, Http};
+
+    fn fixtures_server() -> TestServer {
+        let app = axum::Router::new().nest(
+            "/8",
+            super::route_with(Arc::new(Fixtures::new("assets/pokemon"))),
+        );
+        TestServer::new(app).unwrap()
+    }
+
+    #[tokio::test]
+    async fn task1() {
+        fixtures_server()
+            .get("/8/weight/25")
+            .await
+            .assert_json(&6.0);
+    }
+
+    #[tokio::test]
+    async fn task2() {
+        fixtures_server()
+            .get("/8/drop/25")
+            .await
+            .assert_json(&84.10707461325713);
+    }
+
+    #[tokio::test]
+    async fn unknown_id() {
+        let res = fixtures_server().get("/8/weight/99999").await;
+        res.assert_status_not_found();
+        res.assert_json_contains(&serde_json::json!({"status": 404}));
+    }
+
+    #[tokio::test]
+    async fn upstream_failure() {
+        // nothing listens on port 9 (discard) in the test sandbox
+        let app = axum::Router::new().nest(
+            "/8",
+            super::route_with(Arc::new(Http::new("http://127.0.0.1:9"))),
+        );
+        TestServer::new(app)
+            .unwrap()
+            .get("/8/weight/25")
+            .await
+            .assert_status(StatusCode::BAD_GATEWAY);
+    }
 }
@@ -0,0 +1,189 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures_util::future::BoxFuture;
+
+use crate::error::AppError;
+
+#[derive(Clone, serde::Deserialize)]
+pub(super) struct Pokemon {
+    /// In hectograms, as served by PokéAPI.
+    pub(super) weight: f64,
+}
+
+pub(super) trait PokemonSource: Send + Sync {
+    fn pokemon(&self, id: u32) -> BoxFuture<'_, Result<Pokemon, AppError>>;
+}
+
+/// How long a lookup may take, connecting and reading the body included.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(super) struct Http {
+    client: reqwest::Client,
+    base: String,
+}
+
+impl Http {
+    pub(super) fn new(base: impl Into<String>) -> Self {
+        Http {
+            client: reqwest::Client::builder()
+                .timeout(HTTP_TIMEOUT)
+                .build()
+                .expect("http client with a timeout"),
+            base: base.into(),
+        }
+    }
+}
+
+impl PokemonSource for Http {
+    fn pokemon(&self, id: u32) -> BoxFuture<'_, Result<Pokemon, AppError>> {
+        Box::pin(async move {
+            let response = self
+                .client
+                .get(format!("{}/{}", self.base, id))
+                .send()
+                .await?
+                .error_for_status()?;
+            response
+                .json()
+                .await
+                .map_err(|e| AppError::BadGateway(e.to_string()))
+        })
+    }
+}
+
+/// Reads `<dir>/<id>.json`, one PokéAPI response per file.
+pub(super) struct Fixtures {
+    dir: PathBuf,
+}
+
+impl Fixtures {
+    pub(super) fn new(dir: impl Into<PathBuf>) -> Self {
+        Fixtures { dir: dir.into() }
+    }
+}
+
+impl PokemonSource for Fixtures {
+    fn pokemon(&self, id: u32) -> BoxFuture<'_, Result<Pokemon, AppError>> {
+        Box::pin(async move {
+            let bytes = match tokio::fs::read(self.dir.join(format!("{id}.json"))).await {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(AppError::NotFound(format!("no pokemon with id {id}")));
+                }
+                Err(e) => return Err(AppError::Internal(e.to_string())),
+            };
+            serde_json::from_slice(&bytes).map_err(|e| AppError::BadGateway(e.to_string()))
+        })
+    }
+}
+
+/// Keeps successful lookups of the wrapped source for `ttl`.
+pub(super) struct Cached {
+    inner: Arc<dyn PokemonSource>,
+    ttl: Duration,
+    entries: Mutex<HashMap<u32, (Instant, Pokemon)>>,
+}
+
+impl Cached {
+    pub(super) fn new(inner: Arc<dyn PokemonSource>, ttl: Duration) -> Self {
+        Cached {
+            inner,
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl PokemonSource for Cached {
+    fn pokemon(&self, id: u32) -> BoxFuture<'_, Result<Pokemon, AppError>> {
+        Box::pin(async move {
+            if let Some((at, pokemon)) = self.entries.lock().unwrap().get(&id) {
+                if at.elapsed() < self.ttl {
+                    return Ok(pokemon.clone());
+                }
+            }
+
+            let pokemon = self.inner.pokemon(id).await?;
+            let mut entries = self.entries.lock().unwrap();
+            entries.retain(|_, (at, _)| at.elapsed() < self.ttl);
+            entries.insert(id, (Instant::now(), pokemon.clone()));
+            Ok(pokemon)
+        })
+    }
+}
+
+/// Picks the source from the environment:
+///
+/// - `POKEMON_SOURCE`: `http` (default) or `fixtures`
+/// - `POKEMON_API`: base url for `http`, defaults to PokéAPI
+/// - `POKEMON_FIXTURES`: directory for `fixtures`, defaults to `assets/pokemon`
+/// - `POKEMON_CACHE_TTL`: cache lifetime in seconds, `0` disables the cache
+pub(super) fn from_env() -> Arc<dyn PokemonSource> {
+    let var = |key: &str| std::env::var(key).ok();
+
+    let source: Arc<dyn PokemonSource> = match var("POKEMON_SOURCE").as_deref() {
+        Some("fixtures") => Arc::new(Fixtures::new(
+            var("POKEMON_FIXTURES").unwrap_or_else(|| String::from("assets/pokemon")),
+        )),
+        Some("http") | None => Arc::new(Http::new(
+            var("POKEMON_API").unwrap_or_else(|| String::from(super::API)),
+        )),
+        // a typo must not quietly send lookups somewhere else
+        Some(other) => panic!("unknown POKEMON_SOURCE {other}"),
+    };
+
+    match var("POKEMON_CACHE_TTL").map(|ttl| ttl.parse::<u64>()) {
+        Some(Ok(0)) => source,
+        Some(Ok(secs)) => Arc::new(Cached::new(source, Duration::from_secs(secs))),
+        None | Some(Err(_)) => Arc::new(Cached::new(source, Duration::from_secs(300))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use futures_util::future::BoxFuture;
+
+    use super::{Cached, Pokemon, PokemonSource};
+    use crate::error::AppError;
+
+    #[derive(Default)]
+    struct Counting(AtomicUsize);
+
+    impl PokemonSource for Counting {
+        fn pokemon(&self, _id: u32) -> BoxFuture<'_, Result<Pokemon, AppError>> {
+            let calls = self.0.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                Ok(Pokemon {
+                    weight: calls as f64,
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_expires() {
+        let inner = Arc::new(Counting::default());
+        let cached = Cached::new(inner.clone(), Duration::from_millis(50));
+
+        assert_eq!(cached.pokemon(1).await.unwrap().weight, 0.0);
+        assert_eq!(cached.pokemon(1).await.unwrap().weight, 0.0);
+        assert_eq!(cached.pokemon(2).await.unwrap().weight, 1.0);
+        assert_eq!(inner.0.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(cached.pokemon(1).await.unwrap().weight, 2.0);
+    }
+}
@@ -2,24 +2,57 @@ use axum::{extract::Multipart, routing::post, Json, Router};
 use image::GenericImageView;
 use tower_http::services::ServeDir;
 
+use crate::error::AppError;
+
 pub(super) fn route() -> Router {
     Router::new()
         .nest_service("/assets", ServeDir::new("assets"))
         .route("/red_pixels", post(count_pixels))
 }
 
-async fn count_pixels(mut multipart: Multipart) -> Json<usize> {
-    let Some(field) = multipart.next_field().await.unwrap() else {
-        return axum::Json(0);
+async fn count_pixels(mut multipart: Multipart) -> Result<Json<usize>, AppError> {
+    let Some(field) = multipart.next_field().await? else {
+        return Ok(Json(0));
     };
 
-    let img = image::load_from_memory(field.bytes().await.unwrap().as_ref()).unwrap();
+    let img = image::load_from_memory(field.bytes().await?.as_ref())?;
 
-    img.pixels()
+    Ok(img
+        .pixels()
         .filter(|x| {
             let [r, g, b, _] = x.2 .0;
             u16::from(r) > (u16::from(g) + u16::from(b))
         })
         .count()
-        .into()
+        .into())
+}
+
+#[cfg(test)]
+mod test {
+    use axum_test::multipart::{MultipartForm, Part};
+
+    use crate::days::routes_test;
+
+    #[tokio::test]
+    async fn task2() {
+        let image = include_bytes!("../../assets/decoration.png");
+        routes_test()
+            .await
+            .post("/11/red_pixels")
+            .multipart(MultipartForm::new().add_part("image", Part::bytes(image.as_slice())))
+            .await
+            .assert_json(&73034);
+    }
+
+    #[tokio::test]
+    async fn malformed() {
+        let res = routes_test()
+            .await
+            .post("/11/red_pixels")
+            .multipart(MultipartForm::new().add_part("image", Part::bytes(b"not a png".as_slice())))
+            .expect_failure()
+            .await;
+        res.assert_status(axum::http::StatusCode::UNPROCESSABLE_ENTITY);
+        res.assert_json_contains(&serde_json::json!({"status": 422}));
+    }
 }
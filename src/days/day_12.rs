@@ -15,6 +15,8 @@ use reqwest::StatusCode;
 use ulid::Ulid;
 use uuid::Uuid;
 
+use crate::error::{AppError, JsonBody};
+
 type SharedState = Arc<Mutex<HashMap<String, Instant>>>;
 
 pub(super) fn route() -> Router {
@@ -44,14 +46,16 @@ async fn get_string(Path(string): Path<String>, State(app_state): State<SharedSt
         .map_or(Json(0), |value| Json(value.elapsed().as_secs()))
 }
 
-async fn convert_ulids_to_uuids(Json(payload): Json<Vec<String>>) -> Json<Vec<String>> {
-    Json(
+async fn convert_ulids_to_uuids(
+    JsonBody(payload): JsonBody<Vec<String>>,
+) -> Result<Json<Vec<String>>, AppError> {
+    Ok(Json(
         payload
             .iter()
             .rev()
-            .map(|ulid| Uuid::from_u128(Ulid::from_string(&ulid).unwrap().0).to_string())
-            .collect::<Vec<String>>(),
-    )
+            .map(|ulid| Ok(Uuid::from_u128(Ulid::from_string(ulid)?.0).to_string()))
+            .collect::<Result<Vec<String>, AppError>>()?,
+    ))
 }
 
 #[derive(serde::Serialize, Default)]
@@ -66,34 +70,36 @@ struct Task3Output {
 }
 async fn process_ulids(
     Path(weekday): Path<u8>,
-    Json(payload): Json<Vec<String>>,
-) -> Json<Task3Output> {
+    JsonBody(payload): JsonBody<Vec<String>>,
+) -> Result<Json<Task3Output>, AppError> {
     let now = Utc::now();
-    let weekday = Weekday::try_from(weekday).unwrap();
-    Json(
-        payload
-            .iter()
-            .fold(Task3Output::default(), |mut acc, ulid_str| {
-                let ulid = ulid_str.parse::<Ulid>().unwrap();
-                let time_stamp = ulid.timestamp_ms() as i64;
-                let date_time = Utc.timestamp_millis_opt(time_stamp).unwrap();
-
-                if date_time > now {
-                    acc.in_the_future += 1;
-                }
-                if date_time.weekday() == weekday {
-                    acc.weekday += 1;
-                }
-                if date_time.month() == 12 && date_time.day() == 24 {
-                    acc.christmas_eve += 1;
-                }
-                if ulid.0 & 1 == 1 {
-                    acc.lsb_is_1 += 1;
-                }
-
-                acc
-            }),
-    )
+    let weekday = Weekday::try_from(weekday).map_err(AppError::bad_request)?;
+    payload
+        .iter()
+        .try_fold(Task3Output::default(), |mut acc, ulid_str| {
+            let ulid = ulid_str.parse::<Ulid>()?;
+            let time_stamp = ulid.timestamp_ms() as i64;
+            let date_time = Utc
+                .timestamp_millis_opt(time_stamp)
+                .single()
+                .ok_or_else(|| AppError::bad_request("ulid timestamp out of range"))?;
+
+            if date_time > now {
+                acc.in_the_future += 1;
+            }
+            if date_time.weekday() == weekday {
+                acc.weekday += 1;
+            }
+            if date_time.month() == 12 && date_time.day() == 24 {
+                acc.christmas_eve += 1;
+            }
+            if ulid.0 & 1 == 1 {
+                acc.lsb_is_1 += 1;
+            }
+
+            Ok(acc)
+        })
+        .map(Json)
 }
 
 #[cfg(test)]
@@ -177,4 +183,22 @@ mod test {
               "LSB is 1": 5
             }));
     }
+
+    #[tokio::test]
+    async fn malformed() {
+        let server = routes_test().await;
+        let res = server
+            .post("/12/ulids")
+            .json(&json!(["not-a-ulid"]))
+            .expect_failure()
+            .await;
+        res.assert_status_bad_request();
+        res.assert_json_contains(&json!({"status": 400}));
+        server
+            .post("/12/ulids/9")
+            .json(&json!(["01BJQ0E1C3Z56ABCD0E11HYX4M"]))
+            .expect_failure()
+            .await
+            .assert_status_bad_request();
+    }
 }
@@ -1,12 +1,15 @@
 use axum::{
-    extract::State,
-    response::IntoResponse,
+    extract::{Query, State},
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
 use reqwest::StatusCode;
 use sqlx::SqlitePool;
 
+use super::store;
+use crate::{error::AppError, records::Records};
+
 pub(super) fn route(pool: SqlitePool) -> Router {
     Router::new()
         .route("/sql", get(sql))
@@ -14,76 +17,58 @@ pub(super) fn route(pool: SqlitePool) -> Router {
         .route("/orders", post(insert_orders))
         .route("/orders/total", get(total))
         .route("/orders/popular", get(popular))
+        .route("/orders/series", get(series))
+        .route("/orders/trend", get(trend))
         .with_state(pool)
 }
 
-async fn sql(State(pool): State<SqlitePool>) -> Json<i32> {
-    Json(
+async fn sql(State(pool): State<SqlitePool>) -> Result<Json<i32>, AppError> {
+    Ok(Json(
         sqlx::query_scalar("SELECT 20231213")
             .fetch_one(&pool)
-            .await
-            .unwrap(),
-    )
+            .await?,
+    ))
 }
 
-async fn reset(State(pool): State<SqlitePool>) -> impl IntoResponse {
-    let result = sqlx::query(
-        "
-        DROP TABLE IF EXISTS orders;
-        CREATE TABLE orders (
-          id INT PRIMARY KEY,
-          region_id INT,
-          gift_name VARCHAR(50),
-          quantity INT
-        )
-    ",
-    )
-    .execute(&pool)
-    .await;
-
-    match result {
-        Ok(_) => StatusCode::OK,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
-    }
+async fn reset(State(pool): State<SqlitePool>) -> Result<impl IntoResponse, AppError> {
+    store::Orders::Day13.clear(&pool).await?;
+    Ok(StatusCode::OK)
 }
 
-#[derive(serde::Deserialize)]
-struct Order {
-    id: i32,
-    region_id: i32,
-    gift_name: String,
-    quantity: i32,
+async fn insert_orders(
+    State(pool): State<SqlitePool>,
+    Query(q): Query<store::IngestQuery>,
+    records: Records,
+) -> Result<Json<store::Report>, AppError> {
+    store::ingest(&pool, store::Orders::Day13, records, q.on_conflict)
+        .await
+        .map(Json)
 }
 
-async fn insert_orders(
+async fn series(
     State(pool): State<SqlitePool>,
-    Json(payload): Json<Vec<Order>>,
-) -> impl IntoResponse {
-    for order in payload {
-        sqlx::query("INSERT INTO orders (id, region_id, gift_name, quantity) VALUES (?, ?, ?, ?)")
-            .bind(order.id)
-            .bind(order.region_id)
-            .bind(order.gift_name)
-            .bind(order.quantity)
-            .execute(&pool)
-            .await
-            .unwrap();
-    }
+    Query(q): Query<store::SeriesQuery>,
+) -> Result<Response, AppError> {
+    store::series(&pool, store::Orders::Day13, q).await
+}
 
-    StatusCode::OK
+async fn trend(
+    State(pool): State<SqlitePool>,
+    Query(q): Query<store::TrendQuery>,
+) -> Result<Response, AppError> {
+    store::trend(&pool, store::Orders::Day13, q).await
 }
 
 #[derive(serde::Serialize)]
 struct Total {
     total: i32,
 }
-async fn total(State(pool): State<SqlitePool>) -> Json<Total> {
-    let total: i32 = sqlx::query_scalar("SELECT SUM(quantity) FROM orders")
+async fn total(State(pool): State<SqlitePool>) -> Result<Json<Total>, AppError> {
+    let total: i32 = sqlx::query_scalar("SELECT COALESCE(SUM(quantity), 0) FROM day13_orders")
         .fetch_one(&pool)
-        .await
-        .unwrap();
+        .await?;
 
-    Json(Total { total })
+    Ok(Json(Total { total }))
 }
 
 #[derive(serde::Serialize)]
@@ -91,15 +76,14 @@ struct PopularGift {
     popular: Option<String>,
 }
 
-async fn popular(State(pool): State<SqlitePool>) -> Json<PopularGift> {
-    Json(PopularGift {
+async fn popular(State(pool): State<SqlitePool>) -> Result<Json<PopularGift>, AppError> {
+    Ok(Json(PopularGift {
         popular: sqlx::query_scalar(
-            "SELECT gift_name FROM orders GROUP BY gift_name ORDER BY SUM(quantity) DESC LIMIT 1",
+            "SELECT gift_name FROM day13_orders GROUP BY gift_name ORDER BY SUM(quantity) DESC LIMIT 1",
         )
         .fetch_optional(&pool)
-        .await
-        .unwrap(),
-    })
+        .await?,
+    }))
 }
 
 #[cfg(test)]
@@ -162,4 +146,105 @@ mod test {
             .await
             .assert_json(&json!({"popular": "Toy Train"}));
     }
+
+    #[tokio::test]
+    async fn series_and_trend() {
+        let server = routes_test().await;
+        server.post("/13/reset").await.assert_status_ok();
+        server
+            .post("/13/orders")
+            .json(&json!([
+              {"id":1,"region_id":2,"gift_name":"Doll","quantity":5,"ordered_at":"2024-12-13T10:00:00Z"},
+              {"id":2,"region_id":3,"gift_name":"Doll","quantity":2,"ordered_at":"2024-12-13T18:00:00Z"},
+              {"id":3,"region_id":2,"gift_name":"Doll","quantity":4,"ordered_at":"2024-12-15T09:00:00Z"}
+            ]))
+            .await
+            .assert_status_ok();
+        server
+            .get("/13/orders/series?by=region")
+            .await
+            .assert_json(&json!([
+              {"bucket": "2024-12-13T00:00:00Z", "key": "2", "total": 5},
+              {"bucket": "2024-12-13T00:00:00Z", "key": "3", "total": 2},
+              {"bucket": "2024-12-15T00:00:00Z", "key": "2", "total": 4}
+            ]));
+        server
+            .get("/13/orders/trend?window=2&region_id=2")
+            .await
+            .assert_json(&json!([
+              {"bucket": "2024-12-13T00:00:00Z", "total": 5, "moving_average": 5.0},
+              {"bucket": "2024-12-14T00:00:00Z", "total": 0, "moving_average": 2.5},
+              {"bucket": "2024-12-15T00:00:00Z", "total": 4, "moving_average": 2.0}
+            ]));
+    }
+
+    #[tokio::test]
+    async fn malformed() {
+        let server = routes_test().await;
+        server.post("/13/reset").await.assert_status_ok();
+        server
+            .get("/13/orders/total")
+            .await
+            .assert_json(&json!({"total": 0}));
+        server
+            .post("/13/orders")
+            .json(&json!([{"id":1,"region_id":2,"gift_name":"Toy Train","quantity":5}]))
+            .await
+            .assert_status_ok();
+        let res = server
+            .post("/13/orders")
+            .json(&json!([{"id":1,"region_id":2,"gift_name":"Toy Train","quantity":5}]))
+            .expect_failure()
+            .await;
+        res.assert_status(reqwest::StatusCode::CONFLICT);
+        res.assert_json_contains(&json!({"status": 409, "failed_ids": [1]}));
+    }
+
+    #[tokio::test]
+    async fn csv_and_ndjson() {
+        let server = routes_test().await;
+        server.post("/13/reset").await.assert_status_ok();
+        server
+            .post("/13/orders")
+            .text("id,region_id,gift_name,quantity\n1,2,\"Toy Train, wooden\",5\n2,2,Doll,8\n")
+            .content_type("text/csv")
+            .await
+            .assert_status_ok();
+        server
+            .post("/13/orders")
+            .text("{\"id\":3,\"region_id\":3,\"gift_name\":\"Doll\",\"quantity\":1}\n")
+            .content_type("application/x-ndjson")
+            .await
+            .assert_status_ok();
+        server
+            .get("/13/orders/total")
+            .await
+            .assert_json(&json!({"total": 14}));
+
+        let res = server
+            .post("/13/orders")
+            .text("id,region_id,gift_name,quantity\n4,2,Doll,lots\n5,2,Doll,1\n6,2\n")
+            .content_type("text/csv")
+            .expect_failure()
+            .await;
+        res.assert_status(reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+        res.assert_json(&json!({
+            "type": "about:blank",
+            "title": "Unprocessable Entity",
+            "status": 422,
+            "detail": "2 rows could not be read",
+            "errors": [
+                {"line": 2, "detail": "quantity: invalid digit found in string"},
+                {"line": 4, "detail": "expected field, but got end of row"}
+            ]
+        }));
+
+        server
+            .post("/13/orders")
+            .text("1,2,Doll,8")
+            .content_type("text/plain")
+            .expect_failure()
+            .await
+            .assert_status(reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
 }
@@ -1,9 +1,11 @@
 use axum::{
     response::{Html, IntoResponse},
     routing::post,
-    Json, Router,
+    Router,
 };
 
+use crate::error::JsonBody;
+
 pub(super) fn route() -> Router {
     Router::new().route("/unsafe", post(unsafe_html))
     .route("/safe", post(safe_html))
@@ -14,7 +16,7 @@ struct Payload {
     content: String,
 }
 
-async fn unsafe_html(Json(payload): Json<Payload>) -> impl IntoResponse {
+async fn unsafe_html(JsonBody(payload): JsonBody<Payload>) -> impl IntoResponse {
     Html(format!(
         r"<html>
   <head>
@@ -36,7 +38,7 @@ fn custom_escape(input: &str) -> String {
         .replace("'", "&#x27;")
 }
 
-async fn safe_html(Json(payload): Json<Payload>) -> impl IntoResponse {
+async fn safe_html(JsonBody(payload): JsonBody<Payload>) -> impl IntoResponse {
     Html(format!(
         r"<html>
   <head>
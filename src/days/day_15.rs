@@ -1,12 +1,9 @@
 use std::iter::zip;
 
-use axum::{
-    response::IntoResponse,
-    routing::{post, Route},
-    Json, Router,
-};
+use axum::{response::IntoResponse, routing::post, Json, Router};
 use reqwest::StatusCode;
-use serde_json::json;
+
+use crate::error::JsonBody;
 
 pub(super) fn route() -> Router {
     Router::new()
@@ -14,11 +11,6 @@ pub(super) fn route() -> Router {
         .route("/game", post(game))
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
-struct Nice {
-    input: String,
-}
-
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 enum Result {
     #[serde(rename = "nice")]
@@ -27,11 +19,6 @@ enum Result {
     Naughty,
 }
 
-#[derive(serde::Deserialize)]
-struct Task1Payload {
-    input: String,
-}
-
 #[derive(serde::Deserialize, serde::Serialize)]
 struct Task1Response {
     result: Result,
@@ -43,7 +30,7 @@ fn extract(payload: serde_json::Value) -> Option<String> {
     }
     None
 }
-async fn nice(Json(payload): Json<serde_json::Value>) -> (StatusCode, Json<Task1Response>) {
+async fn nice(JsonBody(payload): JsonBody<serde_json::Value>) -> (StatusCode, Json<Task1Response>) {
     const FORBIDDEN: &[&str] = &["ab", "cd", "pq", "xy"];
     const VOWELS: &[char] = &['a', 'e', 'i', 'o', 'u', 'y'];
     let Some(input) = extract(payload) else {
@@ -57,10 +44,10 @@ async fn nice(Json(payload): Json<serde_json::Value>) -> (StatusCode, Json<Task1
     let mut vowel_count = 0;
     let mut appear_twice = false;
 
-    for i in 0..input.len() - 1 {
-        let word = &input[i..i + 2];
-        let mut chars = word.chars();
-        let (ch1, ch2) = (chars.next().unwrap(), chars.next().unwrap());
+    let chars = input.chars().collect::<Vec<_>>();
+    for pair in chars.windows(2) {
+        let (ch1, ch2) = (pair[0], pair[1]);
+        let word = String::from_iter(pair);
         if ch1.is_alphabetic() && ch1 == ch2 {
             appear_twice = true;
         }
@@ -69,7 +56,7 @@ async fn nice(Json(payload): Json<serde_json::Value>) -> (StatusCode, Json<Task1
             vowel_count += 1;
         }
 
-        if FORBIDDEN.contains(&word) {
+        if FORBIDDEN.contains(&word.as_str()) {
             return (
                 StatusCode::BAD_REQUEST,
                 Json(Task1Response {
@@ -174,7 +161,7 @@ impl IntoResponse for Reason {
 }
 
 #[tracing::instrument(ret)]
-async fn game(Json(payload): Json<serde_json::Value>) -> Reason {
+async fn game(JsonBody(payload): JsonBody<serde_json::Value>) -> Reason {
     let Some(input) = extract(payload) else {
         return Reason::BadRequest;
     };
@@ -304,6 +291,40 @@ mod test {
             .assert_status_bad_request();
     }
 
+    #[tokio::test]
+    async fn odd_input() {
+        let server = routes_test().await;
+        for input in ["", "a", "ééx", "🎄🎄 aei"] {
+            server
+                .post("/15/nice")
+                .json(&json!({ "input": input }))
+                .expect_failure()
+                .await
+                .assert_json(&json!({"result":"naughty"}));
+        }
+        server
+            .post("/15/nice")
+            .json(&json!({"input": "éé aeiou"}))
+            .await
+            .assert_json(&json!({"result":"nice"}));
+
+        let res = server
+            .post("/15/nice")
+            .text("{\"input\": ")
+            .content_type("application/json")
+            .expect_failure()
+            .await;
+        res.assert_status_bad_request();
+        res.assert_header("content-type", "application/problem+json");
+        res.assert_json_contains(&json!({"status": 400}));
+        server
+            .post("/15/nice")
+            .text("{}")
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
     #[tokio::test]
     async fn task2() {
         let server = super::super::routes_test().await;
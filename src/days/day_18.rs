@@ -1,97 +1,90 @@
 use axum::{
     extract::{Path, Query, State},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
 use reqwest::StatusCode;
-use serde_json::json;
 use sqlx::{Row, SqlitePool};
 
+use super::store::{self, Region};
+use crate::{
+    error::{AppError, JsonBody},
+    records::Records,
+};
+
+mod analytics;
+mod rest;
+
 pub(super) fn route(pool: SqlitePool) -> Router {
     Router::new()
         .route("/reset", post(reset))
-        .route("/orders", post(orders))
-        .route("/regions", post(regions))
+        .route("/orders", get(rest::list_orders).post(orders))
+        .route("/orders/series", get(series))
+        .route("/orders/trend", get(trend))
+        .route(
+            "/orders/:id",
+            get(rest::get_order)
+                .put(rest::put_order)
+                .patch(rest::patch_order)
+                .delete(rest::delete_order),
+        )
+        .route("/regions", get(rest::list_regions).post(regions))
+        .route(
+            "/regions/:id",
+            get(rest::get_region)
+                .put(rest::put_region)
+                .patch(rest::patch_region)
+                .delete(rest::delete_region),
+        )
         .route("/regions/total", get(regions_total))
         .route("/regions/top_list/:number", get(top_list))
+        .route("/regions/shares", get(analytics::region_shares))
+        .route("/regions/idle", get(analytics::idle_regions))
+        .route("/regions/pivot", get(analytics::pivot))
+        .route("/gifts/total", get(analytics::gift_totals))
+        .route("/gifts/pareto", get(analytics::pareto))
         .with_state(pool)
 }
 
-async fn reset(State(pool): State<SqlitePool>) -> impl IntoResponse {
-    let result = sqlx::query(
-        "
-    DROP TABLE IF EXISTS regions;
-    DROP TABLE IF EXISTS orders;
-    
-    CREATE TABLE regions (
-      id INT PRIMARY KEY,
-      name VARCHAR(50)
-    );
-    
-    CREATE TABLE orders (
-      id INT PRIMARY KEY,
-      region_id INT,
-      gift_name VARCHAR(50),
-      quantity INT
-    );
-    ",
-    )
-    .execute(&pool)
-    .await;
-
-    match result {
-        Ok(_ok) => StatusCode::OK,
-        Err(_err) => StatusCode::INTERNAL_SERVER_ERROR,
-    }
+async fn reset(State(pool): State<SqlitePool>) -> Result<impl IntoResponse, AppError> {
+    store::Orders::Day18.clear(&pool).await?;
+    Ok(StatusCode::OK)
 }
 
-#[derive(serde::Deserialize)]
-struct Region {
-    id: i32,
-    name: String,
+async fn orders(
+    State(pool): State<SqlitePool>,
+    Query(q): Query<store::IngestQuery>,
+    records: Records,
+) -> Result<Json<store::Report>, AppError> {
+    store::ingest(&pool, store::Orders::Day18, records, q.on_conflict)
+        .await
+        .map(Json)
 }
 
-#[derive(serde::Deserialize)]
-struct Order {
-    id: i32,
-    region_id: i32,
-    gift_name: String,
-    quantity: i32,
+async fn series(
+    State(pool): State<SqlitePool>,
+    Query(q): Query<store::SeriesQuery>,
+) -> Result<Response, AppError> {
+    store::series(&pool, store::Orders::Day18, q).await
 }
 
-async fn orders(
+async fn trend(
     State(pool): State<SqlitePool>,
-    Json(payload): Json<Vec<Order>>,
-) -> impl IntoResponse {
-    for order in payload {
-        sqlx::query("INSERT INTO orders (id, region_id, gift_name, quantity) VALUES (?, ?, ?, ?)")
-            .bind(order.id)
-            .bind(order.region_id)
-            .bind(order.gift_name)
-            .bind(order.quantity)
-            .execute(&pool)
-            .await
-            .unwrap();
-    }
-
-    StatusCode::OK
+    Query(q): Query<store::TrendQuery>,
+) -> Result<Response, AppError> {
+    store::trend(&pool, store::Orders::Day18, q).await
 }
 
 async fn regions(
     State(pool): State<SqlitePool>,
-    Json(payload): Json<Vec<Region>>,
-) -> impl IntoResponse {
+    JsonBody(payload): JsonBody<Vec<Region>>,
+) -> Result<impl IntoResponse, AppError> {
     for region in payload {
-        sqlx::query("INSERT INTO regions (id, name) VALUES (?, ?)")
-            .bind(region.id)
-            .bind(region.name)
-            .execute(&pool)
-            .await
-            .unwrap();
+        region.insert(&pool).await?;
     }
 
-    StatusCode::OK
+    Ok(StatusCode::OK)
 }
 
 #[derive(sqlx::FromRow, serde::Serialize)]
@@ -100,7 +93,7 @@ struct RegionTotal {
     total: i32,
 }
 
-async fn regions_total(State(pool): State<SqlitePool>) -> impl IntoResponse {
+async fn regions_total(State(pool): State<SqlitePool>) -> Result<impl IntoResponse, AppError> {
     let result = sqlx::query_as::<_, RegionTotal>(
         "
     SELECT
@@ -117,10 +110,9 @@ async fn regions_total(State(pool): State<SqlitePool>) -> impl IntoResponse {
     ",
     )
     .fetch_all(&pool)
-    .await
-    .unwrap();
+    .await?;
 
-    Json(result)
+    Ok(Json(result))
 }
 
 #[derive(serde::Serialize, Debug)]
@@ -129,9 +121,12 @@ struct RegionTopGifts {
     top_gifts: Vec<String>,
 }
 
-async fn top_list(Path(number): Path<i32>, State(pool): State<SqlitePool>) -> impl IntoResponse {
+async fn top_list(
+    Path(number): Path<i32>,
+    State(pool): State<SqlitePool>,
+) -> Result<impl IntoResponse, AppError> {
     if number < 1 {
-        return Json(
+        return Ok(Json(
             sqlx::query("SELECT name as region FROM regions")
                 .map(|x: sqlx::sqlite::SqliteRow| {
                     let region: String = x.get("region");
@@ -141,12 +136,11 @@ async fn top_list(Path(number): Path<i32>, State(pool): State<SqlitePool>) -> im
                     }
                 })
                 .fetch_all(&pool)
-                .await
-                .unwrap(),
-        );
+                .await?,
+        ));
     }
 
-    sqlx::query(
+    let result = sqlx::query(
         r"
 SELECT region, group_concat(gift_name, ', ') as top_gifts FROM (
   SELECT regions.name as region, gift_name, row_number() OVER (PARTITION BY regions.name order by regions.name ASC, SUM(quantity) DESC, gift_name ASC) as row_num
@@ -167,8 +161,14 @@ group by region;",
         }
     })
     .fetch_all(&pool)
-    .await.map(|x| Json(x.into_iter().filter(|y| !y.region.is_empty()).collect::<Vec<RegionTopGifts>>()))
-    .unwrap()
+    .await?;
+
+    Ok(Json(
+        result
+            .into_iter()
+            .filter(|y| !y.region.is_empty())
+            .collect::<Vec<RegionTopGifts>>(),
+    ))
 }
 
 #[cfg(test)]
@@ -264,4 +264,37 @@ mod tests {
           {"region":"South Pole","top_gifts":["Doll","Toy Train"]}
         ]));
     }
+
+    #[tokio::test]
+    async fn ndjson_orders() {
+        let server = routes_test().await;
+        server.post("/18/reset").await.assert_status_ok();
+        server
+            .post("/18/regions")
+            .json(&json!([{"id":1,"name":"North Pole"}]))
+            .await
+            .assert_status_ok();
+        server
+            .post("/18/orders")
+            .text(
+                "{\"id\":1,\"region_id\":1,\"gift_name\":\"Doll\",\"quantity\":5}\n\
+                 {\"id\":2,\"region_id\":1,\"gift_name\":\"Doll\",\"quantity\":3}\n",
+            )
+            .content_type("application/x-ndjson")
+            .await
+            .assert_status_ok();
+        server
+            .get("/18/regions/total")
+            .await
+            .assert_json(&json!([{"region": "North Pole", "total": 8}]));
+
+        let res = server
+            .post("/18/orders")
+            .text("{\"id\":3,\"region_id\":1,\n\"gift_name\":\"Doll\",\"quantity\":-}\n")
+            .content_type("application/x-ndjson")
+            .expect_failure()
+            .await;
+        res.assert_status(reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+        res.assert_json_contains(&json!({"errors": [{"line": 1}]}));
+    }
 }
@@ -0,0 +1,455 @@
+//! Reports over the orders for planners, each as JSON or, with
+//! `?format=csv`, as CSV.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+
+use crate::days::store::{csv_response, Format, Region};
+use crate::error::AppError;
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 500;
+/// Regions times gifts the pivot fills in, zeroes included.
+const MAX_PIVOT_CELLS: i64 = 100_000;
+
+#[derive(serde::Deserialize)]
+pub(super) struct GiftQuery {
+    /// Only count the orders of this region.
+    region_id: Option<i32>,
+    /// Gifts returned, 50 by default and at most 500.
+    limit: Option<u32>,
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow)]
+struct GiftTotal {
+    gift: String,
+    total: i64,
+    /// How many regions ordered the gift.
+    regions: i64,
+    /// Of the quantity of all gifts.
+    share: f64,
+}
+
+pub(super) async fn gift_totals(
+    Query(q): Query<GiftQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Response, AppError> {
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT
+          gift_name AS gift,
+          SUM(quantity) AS total,
+          COUNT(DISTINCT region_id) AS regions,
+          CAST(SUM(quantity) AS REAL) / SUM(SUM(quantity)) OVER () AS share
+        FROM orders",
+    );
+    if let Some(region_id) = q.region_id {
+        query.push(" WHERE region_id = ").push_bind(region_id);
+    }
+    let limit = q.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    query
+        .push(" GROUP BY gift_name ORDER BY total DESC, gift ASC LIMIT ")
+        .push_bind(limit);
+    let rows: Vec<GiftTotal> = query.build_query_as().fetch_all(&pool).await?;
+    q.format.respond(&rows)
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct ShareQuery {
+    region_id: Option<i32>,
+    /// Only the gifts ranked this high or better in their region, at most 500.
+    top: Option<u32>,
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow)]
+struct GiftShare {
+    region: String,
+    gift: String,
+    total: i64,
+    /// Of the quantity of all gifts of the region.
+    share: f64,
+    rank: i64,
+}
+
+pub(super) async fn region_shares(
+    Query(q): Query<ShareQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Response, AppError> {
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT region, gift, total, share, rank FROM (
+          SELECT
+            regions.id AS region_id,
+            regions.name AS region,
+            gift_name AS gift,
+            SUM(quantity) AS total,
+            CAST(SUM(quantity) AS REAL)
+              / SUM(SUM(quantity)) OVER (PARTITION BY regions.id) AS share,
+            RANK() OVER (PARTITION BY regions.id ORDER BY SUM(quantity) DESC) AS rank
+          FROM orders JOIN regions ON regions.id = orders.region_id
+          GROUP BY regions.id, gift_name
+        ) WHERE 1 = 1",
+    );
+    if let Some(region_id) = q.region_id {
+        query.push(" AND region_id = ").push_bind(region_id);
+    }
+    if let Some(top) = q.top {
+        query
+            .push(" AND rank <= ")
+            .push_bind(top.clamp(1, MAX_LIMIT));
+    }
+    query.push(" ORDER BY region ASC, region_id ASC, rank ASC, gift ASC");
+    let rows: Vec<GiftShare> = query.build_query_as().fetch_all(&pool).await?;
+    q.format.respond(&rows)
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct ParetoQuery {
+    /// Percent of the quantity the gifts should cover, 80 by default.
+    coverage: Option<f64>,
+    region_id: Option<i32>,
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow)]
+struct ParetoGift {
+    rank: i64,
+    gift: String,
+    total: i64,
+    /// Of all gifts, this one and those ranked above it together.
+    cumulative_share: f64,
+}
+
+/// The fewest best-selling gifts that together make up `coverage` percent of
+/// the quantity ordered.
+pub(super) async fn pareto(
+    Query(q): Query<ParetoQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Response, AppError> {
+    let coverage = q.coverage.unwrap_or(80.0);
+    if !(coverage > 0.0 && coverage <= 100.0) {
+        return Err(AppError::bad_request(
+            "coverage must be a percentage above 0 and at most 100",
+        ));
+    }
+
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "WITH totals AS (
+          SELECT gift_name, SUM(quantity) AS total FROM orders",
+    );
+    if let Some(region_id) = q.region_id {
+        query.push(" WHERE region_id = ").push_bind(region_id);
+    }
+    query
+        .push(
+            " GROUP BY gift_name
+        ), running AS (
+          SELECT
+            ROW_NUMBER() OVER ranked AS rank,
+            gift_name AS gift,
+            total,
+            SUM(total) OVER ranked AS cumulative,
+            SUM(total) OVER () AS volume
+          FROM totals
+          WINDOW ranked AS (ORDER BY total DESC, gift_name ASC ROWS UNBOUNDED PRECEDING)
+        )
+        SELECT rank, gift, total, CAST(cumulative AS REAL) / volume AS cumulative_share
+        FROM running
+        WHERE cumulative - total < volume * ",
+        )
+        .push_bind(coverage / 100.0)
+        .push(" ORDER BY rank");
+    let rows: Vec<ParetoGift> = query.build_query_as().fetch_all(&pool).await?;
+    q.format.respond(&rows)
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct FormatQuery {
+    #[serde(default)]
+    format: Format,
+}
+
+pub(super) async fn idle_regions(
+    Query(q): Query<FormatQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Response, AppError> {
+    let rows: Vec<Region> = sqlx::query_as(
+        "SELECT id, name FROM regions
+        WHERE NOT EXISTS (SELECT 1 FROM orders WHERE orders.region_id = regions.id)
+        ORDER BY name ASC, id ASC",
+    )
+    .fetch_all(&pool)
+    .await?;
+    q.format.respond(&rows)
+}
+
+#[derive(sqlx::FromRow)]
+struct Cell {
+    region_id: i32,
+    region: String,
+    /// `None` for a region without orders.
+    gift: Option<String>,
+    quantity: i64,
+    total: i64,
+}
+
+#[derive(serde::Serialize)]
+struct PivotRow {
+    region: String,
+    gifts: BTreeMap<String, i64>,
+    total: i64,
+}
+
+/// A row per region and a column per gift, every region and gift included,
+/// as long as that's at most [`MAX_PIVOT_CELLS`].
+pub(super) async fn pivot(
+    Query(q): Query<FormatQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Response, AppError> {
+    let (regions, gifts): (i64, i64) = sqlx::query_as(
+        "SELECT
+          (SELECT COUNT(*) FROM regions),
+          (SELECT COUNT(DISTINCT gift_name) FROM orders)",
+    )
+    .fetch_one(&pool)
+    .await?;
+    if regions.saturating_mul(gifts) > MAX_PIVOT_CELLS {
+        return Err(AppError::bad_request(format!(
+            "{regions} regions by {gifts} gifts is more than {MAX_PIVOT_CELLS} cells"
+        )));
+    }
+
+    let cells: Vec<Cell> = sqlx::query_as(
+        "SELECT
+          regions.id AS region_id,
+          regions.name AS region,
+          gift_name AS gift,
+          COALESCE(SUM(quantity), 0) AS quantity,
+          COALESCE(SUM(SUM(quantity)) OVER (PARTITION BY regions.id), 0) AS total
+        FROM regions LEFT JOIN orders ON orders.region_id = regions.id
+        GROUP BY regions.id, gift_name
+        ORDER BY regions.name ASC, regions.id ASC",
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let gifts: BTreeSet<&str> = cells.iter().filter_map(|c| c.gift.as_deref()).collect();
+    let mut rows: Vec<(i32, PivotRow)> = vec![];
+    for cell in &cells {
+        if rows.last().is_none_or(|(id, _)| *id != cell.region_id) {
+            let row = PivotRow {
+                region: cell.region.clone(),
+                gifts: gifts.iter().map(|g| (g.to_string(), 0)).collect(),
+                total: cell.total,
+            };
+            rows.push((cell.region_id, row));
+        }
+        if let (Some(gift), Some((_, row))) = (&cell.gift, rows.last_mut()) {
+            row.gifts.insert(gift.clone(), cell.quantity);
+        }
+    }
+    let rows: Vec<PivotRow> = rows.into_iter().map(|(_, row)| row).collect();
+
+    match q.format {
+        Format::Json => Ok(Json(rows).into_response()),
+        Format::Csv => {
+            // the gift columns aren't known up front, so no `serialize` here
+            let mut csv = csv::Writer::from_writer(vec![]);
+            let header = ["region"].into_iter().chain(gifts.iter().copied());
+            csv.write_record(header.chain(["total"]))
+                .map_err(|e| AppError::Internal(e.to_string()))?;
+            for row in &rows {
+                let record = [row.region.clone()]
+                    .into_iter()
+                    .chain(row.gifts.values().map(ToString::to_string))
+                    .chain([row.total.to_string()]);
+                csv.write_record(record)
+                    .map_err(|e| AppError::Internal(e.to_string()))?;
+            }
+            csv_response(csv)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use crate::days::routes_test;
+
+    async fn seeded() -> axum_test::TestServer {
+        let server = routes_test().await;
+        server.post("/18/reset").await.assert_status_ok();
+        server
+            .post("/18/regions")
+            .json(&json!([
+              {"id":1,"name":"North Pole"},
+              {"id":2,"name":"South Pole"},
+              {"id":3,"name":"Kiribati"}
+            ]))
+            .await;
+        server
+            .post("/18/orders")
+            .json(&json!([
+              {"id":1,"region_id":1,"gift_name":"Doll","quantity":6},
+              {"id":2,"region_id":1,"gift_name":"Drone","quantity":2},
+              {"id":3,"region_id":2,"gift_name":"Doll","quantity":4},
+              {"id":4,"region_id":2,"gift_name":"Toy Train","quantity":4},
+              {"id":5,"region_id":2,"gift_name":"Drone","quantity":4}
+            ]))
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn gift_totals_and_shares() {
+        let server = seeded().await;
+        server.get("/18/gifts/total").await.assert_json(&json!([
+          {"gift": "Doll", "total": 10, "regions": 2, "share": 0.5},
+          {"gift": "Drone", "total": 6, "regions": 2, "share": 0.3},
+          {"gift": "Toy Train", "total": 4, "regions": 1, "share": 0.2}
+        ]));
+        server
+            .get("/18/gifts/total?region_id=1&limit=1")
+            .await
+            .assert_json(&json!([{"gift": "Doll", "total": 6, "regions": 1, "share": 0.75}]));
+
+        server
+            .get("/18/regions/shares?region_id=1")
+            .await
+            .assert_json(&json!([
+              {"region": "North Pole", "gift": "Doll", "total": 6, "share": 0.75, "rank": 1},
+              {"region": "North Pole", "gift": "Drone", "total": 2, "share": 0.25, "rank": 2}
+            ]));
+        let shares = server
+            .get("/18/regions/shares?top=1")
+            .await
+            .json::<Vec<serde_json::Value>>();
+        // South Pole's three gifts tie
+        assert_eq!(shares.len(), 4);
+        assert_eq!(shares[0]["gift"], "Doll");
+        assert_eq!(shares[1]["region"], "South Pole");
+    }
+
+    #[tokio::test]
+    async fn pareto() {
+        let server = seeded().await;
+        server.get("/18/gifts/pareto").await.assert_json(&json!([
+          {"rank": 1, "gift": "Doll", "total": 10, "cumulative_share": 0.5},
+          {"rank": 2, "gift": "Drone", "total": 6, "cumulative_share": 0.8}
+        ]));
+        let gifts = server
+            .get("/18/gifts/pareto?coverage=50")
+            .await
+            .json::<Vec<serde_json::Value>>();
+        assert_eq!(gifts.len(), 1);
+        let gifts = server
+            .get("/18/gifts/pareto?coverage=100&region_id=2")
+            .await
+            .json::<Vec<serde_json::Value>>();
+        assert_eq!(gifts.len(), 3);
+
+        for bad in ["0", "101", "NaN"] {
+            server
+                .get(&format!("/18/gifts/pareto?coverage={bad}"))
+                .expect_failure()
+                .await
+                .assert_status(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_and_pivot() {
+        let server = seeded().await;
+        server
+            .get("/18/regions/idle")
+            .await
+            .assert_json(&json!([{"id": 3, "name": "Kiribati"}]));
+
+        server.get("/18/regions/pivot").await.assert_json(&json!([
+          {"region": "Kiribati", "gifts": {"Doll": 0, "Drone": 0, "Toy Train": 0}, "total": 0},
+          {"region": "North Pole", "gifts": {"Doll": 6, "Drone": 2, "Toy Train": 0}, "total": 8},
+          {"region": "South Pole", "gifts": {"Doll": 4, "Drone": 4, "Toy Train": 4}, "total": 12}
+        ]));
+        let res = server.get("/18/regions/pivot?format=csv").await;
+        res.assert_header("content-type", "text/csv; charset=utf-8");
+        res.assert_text(
+            "region,Doll,Drone,Toy Train,total\n\
+             Kiribati,0,0,0,0\n\
+             North Pole,6,2,0,8\n\
+             South Pole,4,4,4,12\n",
+        );
+    }
+
+    #[tokio::test]
+    async fn pivot_too_large() {
+        let server = routes_test().await;
+        server.post("/18/reset").await.assert_status_ok();
+        let regions: Vec<_> = (1..=400)
+            .map(|id| json!({"id": id, "name": format!("Region {id}")}))
+            .collect();
+        server.post("/18/regions").json(&regions).await;
+        let orders: Vec<_> = (1..=300)
+            .map(|id| json!({"id": id, "region_id": 1, "gift_name": format!("Gift {id}"), "quantity": 1}))
+            .collect();
+        server.post("/18/orders").json(&orders).await;
+        server
+            .get("/18/regions/pivot")
+            .expect_failure()
+            .await
+            .assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn csv_output() {
+        let server = seeded().await;
+        server.get("/18/gifts/total?format=csv").await.assert_text(
+            "gift,total,regions,share\nDoll,10,2,0.5\nDrone,6,2,0.3\nToy Train,4,1,0.2\n",
+        );
+        server
+            .get("/18/regions/idle?format=csv")
+            .await
+            .assert_text("id,name\n3,Kiribati\n");
+        server
+            .get("/18/gifts/total?limit=0&format=csv")
+            .await
+            .assert_text("gift,total,regions,share\nDoll,10,2,0.5\n");
+        server
+            .get("/18/gifts/total?format=xml")
+            .expect_failure()
+            .await
+            .assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn csv_formulas_are_quoted() {
+        let server = routes_test().await;
+        server.post("/18/reset").await.assert_status_ok();
+        server
+            .post("/18/regions")
+            .json(&json!([{"id": 1, "name": "=HYPERLINK(\"x\")"}, {"id": 2, "name": "-1"}]))
+            .await;
+        server
+            .post("/18/orders")
+            .json(&json!([{"id": 1, "region_id": 1, "gift_name": "@SUM(A1)", "quantity": 1}]))
+            .await;
+        server
+            .get("/18/gifts/total?format=csv")
+            .await
+            .assert_text("gift,total,regions,share\n'@SUM(A1),1,1,1.0\n");
+        server
+            .get("/18/regions/pivot?format=csv")
+            .await
+            .assert_text("region,'@SUM(A1),total\n-1,0,0\n\"'=HYPERLINK(\"\"x\"\")\",1,1\n");
+    }
+}
@@ -0,0 +1,394 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+
+use crate::days::store::{
+    self, Order, OrderPatch, OrderQuery, Page, Region, RegionPatch, RegionQuery,
+};
+use crate::error::{AppError, JsonBody};
+
+/// An order as `PUT` takes it, the id comes from the path.
+#[derive(serde::Deserialize)]
+pub(super) struct OrderBody {
+    id: Option<i32>,
+    region_id: i32,
+    gift_name: String,
+    quantity: i32,
+    ordered_at: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "store::ulid")]
+    ulid: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct RegionBody {
+    id: Option<i32>,
+    name: String,
+}
+
+/// A body naming another id than the path is most likely a client bug.
+fn same_id(path: i32, body: Option<i32>) -> Result<(), AppError> {
+    match body {
+        Some(body) if body != path => Err(AppError::bad_request(format!(
+            "the body is for {body}, not {path}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn saved(created: bool) -> StatusCode {
+    if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    }
+}
+
+fn deleted(found: bool, what: &str, id: i32) -> Result<StatusCode, AppError> {
+    if !found {
+        return Err(AppError::NotFound(format!("no {what} {id}")));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn list_orders(
+    Query(q): Query<OrderQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<Page<Order>>, AppError> {
+    Order::list(&pool, &q).await.map(Json)
+}
+
+pub(super) async fn get_order(
+    Path(id): Path<i32>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<Order>, AppError> {
+    Ok(Json(Order::find(&pool, id).await?))
+}
+
+pub(super) async fn put_order(
+    Path(id): Path<i32>,
+    State(pool): State<SqlitePool>,
+    JsonBody(body): JsonBody<OrderBody>,
+) -> Result<(StatusCode, Json<Order>), AppError> {
+    same_id(id, body.id)?;
+    let mut order = Order {
+        id,
+        region_id: body.region_id,
+        gift_name: body.gift_name,
+        quantity: body.quantity,
+        ordered_at: body.ordered_at,
+        ulid: body.ulid,
+    };
+    order.stamp(Utc::now());
+    let created = order.save(&pool).await?;
+    Ok((saved(created), Json(order)))
+}
+
+pub(super) async fn patch_order(
+    Path(id): Path<i32>,
+    State(pool): State<SqlitePool>,
+    JsonBody(patch): JsonBody<OrderPatch>,
+) -> Result<Json<Order>, AppError> {
+    Ok(Json(Order::patch(&pool, id, patch).await?))
+}
+
+pub(super) async fn delete_order(
+    Path(id): Path<i32>,
+    State(pool): State<SqlitePool>,
+) -> Result<StatusCode, AppError> {
+    deleted(Order::delete(&pool, id).await?, "order", id)
+}
+
+pub(super) async fn list_regions(
+    Query(q): Query<RegionQuery>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<Page<Region>>, AppError> {
+    Region::list(&pool, &q).await.map(Json)
+}
+
+pub(super) async fn get_region(
+    Path(id): Path<i32>,
+    State(pool): State<SqlitePool>,
+) -> Result<Json<Region>, AppError> {
+    Ok(Json(Region::find(&pool, id).await?))
+}
+
+pub(super) async fn put_region(
+    Path(id): Path<i32>,
+    State(pool): State<SqlitePool>,
+    JsonBody(body): JsonBody<RegionBody>,
+) -> Result<(StatusCode, Json<Region>), AppError> {
+    same_id(id, body.id)?;
+    let region = Region {
+        id,
+        name: body.name,
+    };
+    let created = region.save(&pool).await?;
+    Ok((saved(created), Json(region)))
+}
+
+pub(super) async fn patch_region(
+    Path(id): Path<i32>,
+    State(pool): State<SqlitePool>,
+    JsonBody(patch): JsonBody<RegionPatch>,
+) -> Result<Json<Region>, AppError> {
+    Ok(Json(Region::patch(&pool, id, patch).await?))
+}
+
+pub(super) async fn delete_region(
+    Path(id): Path<i32>,
+    State(pool): State<SqlitePool>,
+) -> Result<StatusCode, AppError> {
+    deleted(Region::delete(&pool, id).await?, "region", id)
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use crate::days::routes_test;
+
+    #[tokio::test]
+    async fn order_crud() {
+        let server = routes_test().await;
+        server.post("/18/reset").await.assert_status_ok();
+        server
+            .post("/18/regions")
+            .json(&json!([{"id": 1, "name": "North Pole"}, {"id": 2, "name": "South Pole"}]))
+            .await;
+
+        let order = json!({"region_id": 1, "gift_name": "Doll", "quantity": 5});
+        server
+            .put("/18/orders/7")
+            .json(&order)
+            .await
+            .assert_status(StatusCode::CREATED);
+        server
+            .put("/18/orders/7")
+            .json(&json!({
+                "id": 7, "region_id": 2, "gift_name": "Doll", "quantity": 5,
+                "ordered_at": "2024-12-24T10:00:00Z"
+            }))
+            .await
+            .assert_status_ok();
+        server
+            .patch("/18/orders/7")
+            .json(&json!({"quantity": 9}))
+            .await
+            .assert_json(&json!({
+                "id": 7, "region_id": 2, "gift_name": "Doll", "quantity": 9,
+                "ordered_at": "2024-12-24T10:00:00Z"
+            }));
+        server
+            .get("/18/orders/7")
+            .await
+            .assert_json_contains(&json!({"quantity": 9}));
+
+        server
+            .put("/18/orders/7")
+            .json(&json!({"id": 8, "region_id": 2, "gift_name": "Doll", "quantity": 5}))
+            .expect_failure()
+            .await
+            .assert_status_bad_request();
+        server
+            .delete("/18/orders/7")
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        for res in [
+            server.get("/18/orders/7").expect_failure().await,
+            server.delete("/18/orders/7").expect_failure().await,
+            server
+                .patch("/18/orders/7")
+                .json(&json!({"quantity": 1}))
+                .expect_failure()
+                .await,
+        ] {
+            res.assert_status_not_found();
+        }
+    }
+
+    #[tokio::test]
+    async fn region_crud() {
+        let server = routes_test().await;
+        server.post("/18/reset").await.assert_status_ok();
+        server
+            .put("/18/regions/1")
+            .json(&json!({"name": "North Pole"}))
+            .await
+            .assert_status(StatusCode::CREATED);
+        server
+            .patch("/18/regions/1")
+            .json(&json!({"name": "South Pole"}))
+            .await
+            .assert_json(&json!({"id": 1, "name": "South Pole"}));
+        // static routes still win over the id
+        server
+            .get("/18/regions/total")
+            .await
+            .assert_json(&json!([]));
+        server
+            .get("/18/regions?name=South%20Pole")
+            .await
+            .assert_json(&json!({"items": [{"id": 1, "name": "South Pole"}], "next_cursor": null}));
+        server
+            .delete("/18/regions/1")
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        server
+            .get("/18/regions/1")
+            .expect_failure()
+            .await
+            .assert_status_not_found();
+    }
+
+    #[tokio::test]
+    async fn list_filters_and_pages() {
+        let server = routes_test().await;
+        server.post("/18/reset").await.assert_status_ok();
+        server
+            .post("/18/regions")
+            .json(&json!([{"id": 1, "name": "North Pole"}, {"id": 2, "name": "South Pole"}]))
+            .await;
+        server
+            .post("/18/orders")
+            .json(&json!([
+              {"id":1,"region_id":1,"gift_name":"Doll","quantity":5},
+              {"id":2,"region_id":1,"gift_name":"Toy Train","quantity":8},
+              {"id":3,"region_id":2,"gift_name":"Doll","quantity":8},
+              {"id":4,"region_id":1,"gift_name":"Doll","quantity":2},
+              {"id":5,"region_id":1,"gift_name":"Drone","quantity":8}
+            ]))
+            .await;
+
+        let ids = |page: &serde_json::Value| -> Vec<i64> {
+            page["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|o| o["id"].as_i64().unwrap())
+                .collect()
+        };
+
+        let page = server
+            .get("/18/orders?region_id=1&min_quantity=5&sort=-quantity&limit=2")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(ids(&page), [5, 2]);
+        let cursor = page["next_cursor"].as_str().unwrap().to_string();
+        let page = server
+            .get(&format!(
+                "/18/orders?region_id=1&min_quantity=5&sort=-quantity&limit=2&cursor={cursor}"
+            ))
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(ids(&page), [1]);
+        assert!(page["next_cursor"].is_null());
+
+        let page = server
+            .get("/18/orders?gift_name=Doll&max_quantity=5&sort=gift_name")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(ids(&page), [1, 4]);
+
+        let page = server
+            .get("/18/orders?sort=gift_name&limit=3")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(ids(&page), [1, 3, 4]);
+        let cursor = page["next_cursor"].as_str().unwrap().to_string();
+        let page = server
+            .get(&format!("/18/orders?sort=gift_name&cursor={cursor}"))
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(ids(&page), [5, 2]);
+
+        for bad in [
+            "/18/orders?sort=secret".to_string(),
+            "/18/orders?cursor=nope".to_string(),
+            format!("/18/orders?sort=id&cursor={cursor}"),
+        ] {
+            server
+                .get(&bad)
+                .expect_failure()
+                .await
+                .assert_status_bad_request();
+        }
+    }
+
+    #[tokio::test]
+    async fn constraints() {
+        let server = routes_test().await;
+        server.post("/18/reset").await.assert_status_ok();
+        server
+            .put("/18/regions/1")
+            .json(&json!({"name": "North Pole"}))
+            .await;
+        server
+            .put("/18/orders/1")
+            .json(&json!({"region_id": 1, "gift_name": "Doll", "quantity": 5}))
+            .await;
+
+        let res = server
+            .put("/18/orders/2")
+            .json(&json!({"region_id": 9, "gift_name": "Doll", "quantity": 5}))
+            .expect_failure()
+            .await;
+        res.assert_status(StatusCode::CONFLICT);
+        res.assert_json_contains(
+            &json!({"detail": "unknown region, or a region that still has orders"}),
+        );
+        server
+            .delete("/18/regions/1")
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::CONFLICT);
+        server
+            .post("/18/regions")
+            .json(&json!([{"id": 1, "name": "South Pole"}]))
+            .expect_failure()
+            .await
+            .assert_json_contains(&json!({"status": 409, "detail": "duplicate id"}));
+        server
+            .post("/18/orders")
+            .json(&json!([{"id": 3, "region_id": 9, "gift_name": "Doll", "quantity": 1}]))
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::CONFLICT);
+
+        for bad in [
+            json!({"region_id": 1, "gift_name": "Doll", "quantity": 0}),
+            json!({"region_id": 1, "gift_name": "", "quantity": 1}),
+        ] {
+            let res = server.put("/18/orders/2").json(&bad).expect_failure().await;
+            res.assert_status_unprocessable_entity();
+            res.assert_json_contains(&json!({"detail": "invalid value"}));
+        }
+        server
+            .patch("/18/orders/1")
+            .json(&json!({"quantity": -1}))
+            .expect_failure()
+            .await
+            .assert_status_unprocessable_entity();
+        server
+            .get("/18/orders/1")
+            .await
+            .assert_json_contains(&json!({"quantity": 5}));
+
+        // the tables are emptied, not recreated without their constraints
+        server.post("/18/reset").await.assert_status_ok();
+        server
+            .get("/18/regions")
+            .await
+            .assert_json(&json!({"items": [], "next_cursor": null}));
+        server
+            .put("/18/orders/1")
+            .json(&json!({"region_id": 1, "gift_name": "Doll", "quantity": 5}))
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::CONFLICT);
+    }
+}
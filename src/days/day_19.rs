@@ -1,54 +1,190 @@
 use axum::{
     extract::{
-        ws::{WebSocket, WebSocketUpgrade},
-        Path, State,
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::StatusCode,
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        IntoResponse,
     },
-    response::IntoResponse,
     routing::{get, post},
-    Router,
+    Json, Router,
 };
-use futures_util::{SinkExt, StreamExt};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures_util::{stream, Stream, StreamExt};
 use std::{
     collections::HashMap,
+    convert::Infallible,
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc, Mutex,
     },
 };
-use tokio::{spawn, sync::broadcast::Sender};
+use sqlx::SqlitePool;
+use tokio::{select, spawn, sync::mpsc};
+use ulid::{Generator, Ulid};
+
+use crate::error::{AppError, JsonBody};
+use moderation::Rejection;
+
+mod auth;
+mod config;
+mod feed;
+mod heartbeat;
+mod history;
+mod moderation;
+mod rooms;
+mod stats;
 
+/// How many stored messages a client joining a room gets replayed by default.
+const DEFAULT_REPLAY: u32 = 20;
+const MAX_REPLAY: u32 = 500;
+
+/// A tweet as sent to clients. `user` and `message` are all the original
+/// format had, the rest are extras older clients can ignore.
 #[derive(Clone, serde::Serialize)]
 struct Message {
     user: String,
     message: String,
+    #[serde(rename = "type")]
+    kind: Kind,
+    #[serde(serialize_with = "display")]
+    id: Ulid,
+    /// RFC 3339, taken from the id.
+    sent_at: String,
+    /// Set on direct messages, which only the two parties receive.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<String>,
+}
+
+#[derive(Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Chat,
+    Dm,
+}
+
+impl Message {
+    fn new(id: Ulid, user: String, message: String, to: Option<String>) -> Self {
+        Message {
+            user,
+            message,
+            kind: if to.is_some() { Kind::Dm } else { Kind::Chat },
+            id,
+            sent_at: DateTime::<Utc>::from(id.datetime())
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+            to,
+        }
+    }
+
+    /// Whether `user` gets to see this message.
+    fn is_for(&self, user: &str) -> bool {
+        self.to
+            .as_ref()
+            .is_none_or(|to| to == user || self.user == user)
+    }
+}
+
+fn display<T: std::fmt::Display, S: serde::Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Presence {
+    Join { user: String },
+    Leave { user: String },
+    Typing { user: String },
+}
+
+/// Everything broadcast to a room, serialized as-is onto the sockets.
+#[derive(Clone, serde::Serialize)]
+#[serde(untagged)]
+enum Event {
+    Chat(Message),
+    Presence(Presence),
 }
 
 #[derive(Clone)]
 struct Day19State {
     view_count: Arc<AtomicU64>,
-    sockets: Arc<Mutex<HashMap<u64, Sender<Message>>>>,
+    sockets: Arc<Mutex<HashMap<u64, rooms::Room>>>,
+    stats: Arc<Mutex<stats::Stats>>,
+    ids: Arc<Mutex<Generator>>,
+    moderator: Arc<moderation::Moderator>,
+    connections: Arc<heartbeat::Connections>,
+    config: Arc<config::Config>,
+    pool: SqlitePool,
 }
 
-pub(crate) fn route() -> Router {
+pub(crate) fn route(pool: SqlitePool) -> Router {
+    route_with(pool, config::Config::from_env())
+}
+
+fn route_with(pool: SqlitePool, config: config::Config) -> Router {
     Router::new()
         .route("/ws/ping", get(ping_handler))
+        .route("/login", post(auth::login))
         .route("/reset", post(reset))
         .route("/views", get(views))
         .route("/ws/room/:id/user/:user", get(room_handler))
-        .with_state(Day19State {
+        .route("/sse/room/:id/user/:user", get(sse_handler))
+        .route("/rooms/:id/messages", get(history::messages).post(post_message))
+        .route("/rooms/:id/members", get(rooms::members))
+        .route("/rooms/:id/stats", get(stats::room_stats))
+        .route("/users/:user/stats", get(stats::user_stats))
+        .route("/admin/connections", get(heartbeat::connections))
+        .with_state(Day19State::new(pool, config))
+}
+
+impl Day19State {
+    fn new(pool: SqlitePool, config: config::Config) -> Self {
+        Day19State {
             view_count: Arc::new(AtomicU64::new(0)),
             sockets: Arc::new(Mutex::new(HashMap::new())),
-        })
+            stats: Arc::new(Mutex::new(stats::Stats::default())),
+            ids: Arc::new(Mutex::new(Generator::new())),
+            moderator: Arc::new(moderation::Moderator::new(&config)),
+            connections: Arc::new(heartbeat::Connections::default()),
+            config: Arc::new(config),
+            pool,
+        }
+    }
 }
 
-async fn ping_handler(ws: WebSocketUpgrade) -> impl IntoResponse {
-    ws.on_upgrade(ping)
+async fn ping_handler(ws: WebSocketUpgrade, State(state): State<Day19State>) -> impl IntoResponse {
+    ws.on_upgrade(move |ws| ping(ws, state))
 }
 
-async fn ping(mut ws: WebSocket) {
+async fn ping(mut ws: WebSocket, state: Day19State) {
+    let mut heartbeat = heartbeat::Heartbeat::new(&state, heartbeat::Socket::Ping);
     let mut served = false;
-    while let Some(Ok(msg)) = ws.recv().await {
-        if let Ok(msg) = msg.to_text() {
+    loop {
+        let msg = select! {
+            msg = ws.recv() => msg,
+            beat = heartbeat.tick() => match beat {
+                Ok(payload) => {
+                    if ws.send(WsMessage::Ping(payload)).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    tracing::info!(error = %e, "closing ping socket");
+                    break;
+                }
+            },
+        };
+        let Some(Ok(msg)) = msg else {
+            break;
+        };
+        if let WsMessage::Pong(payload) = &msg {
+            heartbeat.pong(payload);
+        } else if let Ok(msg) = msg.to_text() {
             match msg {
                 "serve" => served = true,
                 "ping" if served => {
@@ -60,69 +196,812 @@ async fn ping(mut ws: WebSocket) {
     }
 }
 
-async fn reset(State(state): State<Day19State>) {
-    state.view_count.store(0, Ordering::Relaxed);
+#[derive(serde::Deserialize)]
+struct ResetQuery {
+    room: Option<u64>,
+}
+
+/// Without a scope clears the global view count and every room and user
+/// counter, with `?room=` only that room's counters.
+async fn reset(Query(q): Query<ResetQuery>, State(state): State<Day19State>) {
+    let Ok(mut stats) = state.stats.lock() else {
+        return;
+    };
+    match q.room {
+        Some(room) => stats.reset_room(room),
+        None => {
+            state.view_count.store(0, Ordering::Relaxed);
+            stats.clear();
+        }
+    }
 }
 
 async fn views(State(state): State<Day19State>) -> impl IntoResponse {
     state.view_count.load(Ordering::Relaxed).to_string()
 }
 
+#[derive(serde::Deserialize)]
+struct JoinQuery {
+    history: Option<u32>,
+}
+
 async fn room_handler(
     ws: WebSocketUpgrade,
     Path((id, user)): Path<(u64, String)>,
+    Query(q): Query<JoinQuery>,
     State(state): State<Day19State>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |s| room(s, id, user, state))
+    session: auth::Session,
+) -> Result<impl IntoResponse, AppError> {
+    session.require(&user)?;
+    let replay = q.history.unwrap_or(DEFAULT_REPLAY).min(MAX_REPLAY);
+    let ws = match session.protocol {
+        Some(protocol) => ws.protocols([protocol]),
+        None => ws,
+    };
+    Ok(ws.on_upgrade(move |s| room(s, id, user, replay, state)))
 }
 
+/// What clients send over the websocket.
 #[derive(serde::Deserialize)]
-struct WsMsg {
-    message: String,
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Envelope {
+    Chat { message: String },
+    Dm { to: String, message: String },
+    Typing,
+    Ping,
 }
 
-async fn room(ws: WebSocket, id: u64, user: String, state: Day19State) {
-    let send = {
-        let Ok(mut map) = state.sockets.lock() else {
-            return;
-        };
+/// The tagged envelope, or the original bare `{"message": ...}`.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Incoming {
+    Tagged(Envelope),
+    Legacy { message: String },
+}
 
-        if let Some(ch) = map.get(&id) {
-            ch.clone()
-        } else {
-            let ch = Sender::new(128);
-            map.insert(id, ch.clone());
-            ch
-        }
+async fn room(ws: WebSocket, room_id: u64, user: String, replay: u32, state: Day19State) {
+    let Some(mut feed) = feed::Feed::open(&state, room_id, &user, replay).await else {
+        return;
     };
 
-    let (mut ws_send, mut ws_recv) = ws.split();
+    let (ws_send, mut ws_recv) = ws.split();
 
-    let mut recv = send.subscribe();
-    let recv_task = spawn(async move {
-        while let Ok(message) = recv.recv().await {
-            if let Ok(message) = serde_json::to_string(&message) {
-                if ws_send.send(message.into()).await.is_ok() {
-                    state.view_count.fetch_add(1, Ordering::Relaxed);
-                } else {
-                    return;
+    let notices = feed.notifier();
+    let (control, control_recv) = mpsc::channel(4);
+    let write_timeout = state.config.write_timeout;
+    let mut recv_task = spawn(async move {
+        if let Err(e) = feed::forward(&mut feed, ws_send, control_recv, write_timeout).await {
+            tracing::info!(room = room_id, error = %e, "dropping chat client");
+        }
+    });
+
+    let mut heartbeat = heartbeat::Heartbeat::new(
+        &state,
+        heartbeat::Socket::Room {
+            room: room_id,
+            user: user.clone(),
+        },
+    );
+    loop {
+        let msg = select! {
+            msg = ws_recv.next() => msg,
+            // a client that stopped reading is hung up on, even if it still writes
+            _ = &mut recv_task => break,
+            beat = heartbeat.tick() => match beat {
+                Ok(payload) => {
+                    let _ = control.try_send(WsMessage::Ping(payload));
+                    continue;
                 }
+                Err(e) => {
+                    tracing::info!(room = room_id, error = %e, "dropping chat client");
+                    break;
+                }
+            },
+        };
+        let Some(Ok(msg)) = msg else {
+            break;
+        };
+        let text = match msg {
+            WsMessage::Text(text) => text,
+            WsMessage::Binary(_) => {
+                let _ = notices.try_send(Rejection::Malformed.into());
+                continue;
+            }
+            WsMessage::Pong(payload) => {
+                heartbeat.pong(&payload);
+                continue;
+            }
+            // pings are answered by axum, close ends the stream
+            _ => continue,
+        };
+        let result = match serde_json::from_str::<Incoming>(&text) {
+            Ok(Incoming::Legacy { message } | Incoming::Tagged(Envelope::Chat { message })) => {
+                publish(&state, room_id, &user, message, None).await.map(drop)
+            }
+            Ok(Incoming::Tagged(Envelope::Dm { to, message })) => {
+                publish(&state, room_id, &user, message, Some(to)).await.map(drop)
+            }
+            Ok(Incoming::Tagged(Envelope::Typing)) => {
+                rooms::broadcast(&state, room_id, Event::Presence(Presence::Typing {
+                    user: user.clone(),
+                }));
+                Ok(())
+            }
+            Ok(Incoming::Tagged(Envelope::Ping)) => {
+                let _ = notices.try_send(feed::Notice::Pong);
+                Ok(())
             }
+            Err(_) => Err(Rejection::Malformed),
+        };
+        if let Err(rejection) = result {
+            let _ = notices.try_send(rejection.into());
         }
+    }
+
+    recv_task.abort();
+}
+
+/// Same room feed as the websocket, for clients behind proxies that strip
+/// upgrades. Messages are sent with `POST /rooms/:id/messages`.
+async fn sse_handler(
+    Path((id, user)): Path<(u64, String)>,
+    Query(q): Query<JoinQuery>,
+    State(state): State<Day19State>,
+    session: auth::Session,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, Infallible>>>, AppError> {
+    session.require(&user)?;
+    let replay = q.history.unwrap_or(DEFAULT_REPLAY).min(MAX_REPLAY);
+    let feed = feed::Feed::open(&state, id, &user, replay)
+        .await
+        .ok_or_else(|| AppError::Internal("room state poisoned".to_string()))?;
+
+    // the body only asks for the next event once it has taken the last one,
+    // so that's when the last one counts as delivered
+    let events = stream::unfold((feed, None), |(mut feed, sent)| async move {
+        if let Some(sent) = sent {
+            feed.delivered(&sent);
+        }
+        let outgoing = feed.next().await?;
+        let event = SseEvent::default().data(outgoing.text.clone());
+        Some((Ok(event), (feed, Some(outgoing))))
     });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[derive(serde::Deserialize)]
+struct PostMessage {
+    /// Optional, the author is whoever the session belongs to.
+    user: Option<String>,
+    message: String,
+    /// Sends a direct message instead.
+    to: Option<String>,
+}
+
+async fn post_message(
+    Path(id): Path<u64>,
+    State(state): State<Day19State>,
+    session: auth::Session,
+    JsonBody(body): JsonBody<PostMessage>,
+) -> Result<(StatusCode, Json<Message>), AppError> {
+    if let Some(user) = &body.user {
+        session.require(user)?;
+    }
+    let message = publish(&state, id, &session.user, body.message, body.to).await?;
+    Ok((StatusCode::CREATED, Json(message)))
+}
+
+/// Moderates a tweet, stores it and broadcasts it to whoever is in the room,
+/// whichever transport they're connected with. Direct messages go to `to`
+/// alone and aren't kept in the room's history.
+async fn publish(
+    state: &Day19State,
+    room_id: u64,
+    user: &str,
+    message: String,
+    to: Option<String>,
+) -> Result<Message, Rejection> {
+    if let Some(to) = &to {
+        if !rooms::is_member(state, room_id, to) {
+            return Err(Rejection::UnknownRecipient { to: to.clone() });
+        }
+    }
+    state.moderator.check(room_id, user, &message)?;
+    let id = next_id(state).ok_or(Rejection::Unavailable)?;
+    let message = Message::new(id, user.to_string(), message, to);
+    if message.to.is_none() {
+        if let Err(e) = history::store(&state.pool, room_id, &message).await {
+            tracing::error!(error = %e, room = room_id, "failed to store chat message");
+        }
+    }
+    if let Ok(mut stats) = state.stats.lock() {
+        stats.sent(room_id, user);
+    }
+    rooms::broadcast(state, room_id, Event::Chat(message.clone()));
+    Ok(message)
+}
+
+fn next_id(state: &Day19State) -> Option<Ulid> {
+    state.ids.lock().ok()?.generate().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use axum_test::{TestResponse, TestServer, TestWebSocket};
+    use serde_json::json;
+
+    use super::config::Config;
+
+    const PASSWORD: &str = "cookies";
+
+    const ADMIN_KEY: &str = "north-pole";
 
-    while let Some(Ok(msg)) = ws_recv.next().await {
-        if let Ok(msg) = msg.into_text() {
-            if let Ok(WsMsg { message }) = serde_json::from_str::<WsMsg>(&msg) {
-                if message.len() <= 128 {
-                    let _ = send.send(Message {
-                        user: user.clone(),
-                        message,
-                    });
+    /// Santa, the elf and the grinch may log in.
+    fn config() -> Config {
+        let passwords = ["santa", "elf", "grinch"]
+            .into_iter()
+            .map(|user| (user.to_string(), PASSWORD.to_string()))
+            .collect();
+        Config {
+            passwords,
+            admin_key: Some(ADMIN_KEY.to_string()),
+            ..Default::default()
+        }
+    }
+
+    async fn connections(server: &TestServer) -> TestResponse {
+        server
+            .get("/19/admin/connections")
+            .authorization_bearer(ADMIN_KEY)
+            .await
+    }
+
+    /// Served over a real socket, which websockets need. Upgrades answer
+    /// 101, so success isn't expected by default.
+    async fn server_with(config: Config) -> TestServer {
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        TestServer::new_with_config(
+            axum::Router::new().nest("/19", super::route_with(pool, config)),
+            axum_test::TestServerConfig {
+                transport: Some(axum_test::Transport::HttpRandomPort),
+                expect_success_by_default: false,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    async fn server() -> TestServer {
+        server_with(config()).await
+    }
+
+    #[tokio::test]
+    async fn history_replay() {
+        let server = server().await;
+        let mut santa = connect(&server, "/19/ws/room/1/user/santa", "santa").await;
+        santa
+            .assert_receive_json(&json!({"type": "join", "user": "santa"}))
+            .await;
+        santa.send_json(&json!({"message": "ho"})).await;
+        santa.send_json(&json!({"message": "ho ho"})).await;
+        assert_receive_chat(&mut santa, &json!({"user": "santa", "message": "ho"})).await;
+        assert_receive_chat(&mut santa, &json!({"user": "santa", "message": "ho ho"})).await;
+
+        let mut elf = connect(&server, "/19/ws/room/1/user/elf?history=1", "elf").await;
+        assert_receive_chat(&mut elf, &json!({"user": "santa", "message": "ho ho"})).await;
+        elf.assert_receive_json(&json!({"type": "join", "user": "elf"}))
+            .await;
+        santa
+            .assert_receive_json(&json!({"type": "join", "user": "elf"}))
+            .await;
+        santa.send_json(&json!({"message": "ho ho ho"})).await;
+        assert_receive_chat(&mut elf, &json!({"user": "santa", "message": "ho ho ho"})).await;
+        assert_receive_chat(&mut santa, &json!({"user": "santa", "message": "ho ho ho"})).await;
+
+        // replays aren't counted, live deliveries are: 2 to santa, then 1 each
+        assert_views(&server, "/19/views", "4").await;
+    }
+
+    #[tokio::test]
+    async fn presence() {
+        let server = server().await;
+        let mut santa = connect(&server, "/19/ws/room/3/user/santa", "santa").await;
+        santa
+            .assert_receive_json(&json!({"type": "join", "user": "santa"}))
+            .await;
+        let elf = connect(&server, "/19/ws/room/3/user/elf", "elf").await;
+        santa
+            .assert_receive_json(&json!({"type": "join", "user": "elf"}))
+            .await;
+        server
+            .get("/19/rooms/3/members")
+            .await
+            .assert_json(&json!(["elf", "santa"]));
+
+        elf.close().await;
+        santa
+            .assert_receive_json(&json!({"type": "leave", "user": "elf"}))
+            .await;
+        server
+            .get("/19/rooms/3/members")
+            .await
+            .assert_json(&json!(["santa"]));
+
+        santa.close().await;
+        for _ in 0..50 {
+            if server.get("/19/rooms/3/members").await.json::<Vec<String>>().is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        server.get("/19/rooms/3/members").await.assert_json(&json!([]));
+        // presence events aren't views
+        server.get("/19/views").await.assert_text("0");
+    }
+
+    #[tokio::test]
+    async fn room_and_user_stats() {
+        let server = server().await;
+        let mut santa = connect(&server, "/19/ws/room/4/user/santa?history=0", "santa").await;
+        let mut elf = connect(&server, "/19/ws/room/4/user/elf?history=0", "elf").await;
+        let mut grinch = connect(&server, "/19/ws/room/5/user/grinch?history=0", "grinch").await;
+        // join events
+        let _ = santa.receive_text().await;
+        let _ = santa.receive_text().await;
+        let _ = elf.receive_text().await;
+        let _ = grinch.receive_text().await;
+
+        santa.send_json(&json!({"message": "hi"})).await;
+        let tweet = santa.receive_text().await;
+        assert_eq!(elf.receive_text().await, tweet);
+        grinch.send_json(&json!({"message": "bah"})).await;
+        let _ = grinch.receive_text().await;
+
+        let bytes = tweet.len() as u64;
+        assert_views(&server, "/19/views", "3").await;
+        server.get("/19/rooms/4/stats").await.assert_json(&json!({
+            "messages_sent": 1,
+            "messages_delivered": 2,
+            "bytes_delivered": 2 * bytes
+        }));
+        server.get("/19/users/santa/stats").await.assert_json(&json!({
+            "messages_sent": 1,
+            "messages_delivered": 1,
+            "bytes_delivered": bytes
+        }));
+        server.get("/19/users/elf/stats").await.assert_json(&json!({
+            "messages_sent": 0,
+            "messages_delivered": 1,
+            "bytes_delivered": bytes
+        }));
+
+        server.post("/19/reset?room=4").await.assert_status_ok();
+        server
+            .get("/19/rooms/4/stats")
+            .await
+            .assert_json_contains(&json!({"messages_sent": 0}));
+        server
+            .get("/19/rooms/5/stats")
+            .await
+            .assert_json_contains(&json!({"messages_sent": 1}));
+        server.get("/19/views").await.assert_text("3");
+
+        server.post("/19/reset").await.assert_status_ok();
+        server.get("/19/views").await.assert_text("0");
+        server
+            .get("/19/rooms/5/stats")
+            .await
+            .assert_json_contains(&json!({"messages_sent": 0}));
+    }
+
+    #[tokio::test]
+    async fn sse_and_rest() {
+        let server = server().await;
+        let token = login(&server, "elf").await;
+        let mut elf = reqwest::get(
+            server
+                .server_url(&format!("/19/sse/room/6/user/elf?history=0&token={token}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(elf.headers()["content-type"], "text/event-stream");
+        let mut buf = String::new();
+        assert_eq!(
+            next_sse(&mut elf, &mut buf).await,
+            json!({"type": "join", "user": "elf"})
+        );
+
+        let mut santa = connect(&server, "/19/ws/room/6/user/santa?history=0", "santa").await;
+        santa
+            .assert_receive_json(&json!({"type": "join", "user": "santa"}))
+            .await;
+        assert_eq!(
+            next_sse(&mut elf, &mut buf).await,
+            json!({"type": "join", "user": "santa"})
+        );
+
+        // posted over REST, delivered to both transports
+        server
+            .post("/19/rooms/6/messages")
+            .authorization_bearer(&token)
+            .json(&json!({"user": "elf", "message": "cookies?"}))
+            .await
+            .assert_status(axum::http::StatusCode::CREATED);
+        let tweet = json!({"user": "elf", "message": "cookies?"});
+        assert_receive_chat(&mut santa, &tweet).await;
+        assert_chat(&next_sse(&mut elf, &mut buf).await, &tweet);
+
+        // and the other way around
+        santa.send_json(&json!({"message": "milk"})).await;
+        let tweet = json!({"user": "santa", "message": "milk"});
+        assert_receive_chat(&mut santa, &tweet).await;
+        assert_chat(&next_sse(&mut elf, &mut buf).await, &tweet);
+
+        assert_views(&server, "/19/views", "4").await;
+        server
+            .get("/19/users/elf/stats")
+            .await
+            .assert_json_contains(&json!({"messages_sent": 1, "messages_delivered": 2}));
+        server
+            .get("/19/rooms/6/messages")
+            .await
+            .assert_json_contains(&json!({"messages": [
+                {"user": "elf", "message": "cookies?"},
+                {"user": "santa", "message": "milk"}
+            ]}));
+
+        server
+            .post("/19/rooms/6/messages")
+            .authorization_bearer(token)
+            .json(&json!({"message": "a".repeat(129)}))
+            .expect_failure()
+            .await
+            .assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn sessions() {
+        let server = server().await;
+        let elf = login(&server, "elf").await;
+
+        // anonymous, impersonating and tampered handshakes, on both transports
+        for path in [
+            String::from("/19/ws/room/7/user/elf"),
+            format!("/19/ws/room/7/user/santa?token={elf}"),
+            format!("/19/ws/room/7/user/elf?token=x{elf}"),
+        ] {
+            server
+                .get_websocket(&path)
+                .await
+                .assert_status_unauthorized();
+            server
+                .get(&path.replace("/ws/", "/sse/"))
+                .await
+                .assert_status_unauthorized();
+        }
+        server
+            .post("/19/rooms/7/messages")
+            .authorization_bearer(&elf)
+            .json(&json!({"user": "santa", "message": "ho ho"}))
+            .await
+            .assert_status_unauthorized();
+        server
+            .post("/19/rooms/7/messages")
+            .json(&json!({"user": "elf", "message": "hi"}))
+            .await
+            .assert_status_unauthorized();
+
+        // the token as a subprotocol is echoed back on the handshake
+        let response = server
+            .get_websocket("/19/ws/room/7/user/elf")
+            .add_header("Sec-WebSocket-Protocol", format!("chat, {elf}"))
+            .await;
+        assert_eq!(response.header("sec-websocket-protocol"), elf.as_str());
+        let mut ws = response.into_websocket().await;
+        ws.assert_receive_json(&json!({"type": "join", "user": "elf"}))
+            .await;
+
+        // only with the right password
+        for (user, password) in [("santa", "milk"), ("rudolph", PASSWORD), ("santa", "")] {
+            server
+                .post("/19/login")
+                .json(&json!({"user": user, "password": password}))
+                .await
+                .assert_status_unauthorized();
+        }
+        server
+            .post("/19/login")
+            .json(&json!({"user": " ", "password": PASSWORD}))
+            .await
+            .assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn moderation_frames() {
+        let server = server().await;
+        let mut santa = connect(&server, "/19/ws/room/8/user/santa?history=0", "santa").await;
+        let _ = santa.receive_text().await;
+
+        // the limit is in characters, these are 256 bytes
+        santa.send_json(&json!({"message": "é".repeat(128)})).await;
+        assert_receive_chat(
+            &mut santa,
+            &json!({"user": "santa", "message": "é".repeat(128)}),
+        )
+        .await;
+        santa.send_json(&json!({"message": "é".repeat(129)})).await;
+        santa
+            .assert_receive_json(&json!({
+                "type": "error",
+                "code": "too_long",
+                "max_chars": 128,
+                "detail": "message is longer than 128 characters"
+            }))
+            .await;
+        santa.send_text("ho ho ho").await;
+        santa
+            .assert_receive_json(&json!({
+                "type": "error",
+                "code": "malformed",
+                "detail": "message is not valid chat json"
+            }))
+            .await;
+
+        // the burst is 10, one was used above
+        for i in 0..10 {
+            santa.send_json(&json!({"message": i.to_string()})).await;
+        }
+        let mut limited = false;
+        for _ in 0..10 {
+            let frame = santa.receive_json::<serde_json::Value>().await;
+            limited |= frame["code"] == "rate_limited";
+        }
+        assert!(limited);
+        let token = login(&server, "santa").await;
+        server
+            .post("/19/rooms/8/messages")
+            .authorization_bearer(token)
+            .json(&json!({"message": "please"}))
+            .await
+            .assert_status(axum::http::StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn envelopes() {
+        let server = server().await;
+        let mut santa = connect(&server, "/19/ws/room/9/user/santa?history=0", "santa").await;
+        let mut elf = connect(&server, "/19/ws/room/9/user/elf?history=0", "elf").await;
+        let mut grinch = connect(&server, "/19/ws/room/9/user/grinch?history=0", "grinch").await;
+        for _ in 0..3 {
+            let _ = santa.receive_text().await;
+        }
+        for _ in 0..2 {
+            let _ = elf.receive_text().await;
+        }
+        let _ = grinch.receive_text().await;
+
+        santa
+            .send_json(&json!({"type": "dm", "to": "elf", "message": "psst"}))
+            .await;
+        let dm = json!({"type": "dm", "user": "santa", "to": "elf", "message": "psst"});
+        assert_receive_chat(&mut santa, &dm).await;
+        assert_receive_chat(&mut elf, &dm).await;
+
+        santa.send_json(&json!({"type": "typing"})).await;
+        let typing = json!({"type": "typing", "user": "santa"});
+        elf.assert_receive_json(&typing).await;
+        // the grinch never saw the dm
+        grinch.assert_receive_json(&typing).await;
+
+        santa
+            .send_json(&json!({"type": "chat", "message": "hi all"}))
+            .await;
+        let chat = json!({"type": "chat", "user": "santa", "message": "hi all"});
+        assert_receive_chat(&mut grinch, &chat).await;
+        let _ = santa.receive_text().await;
+
+        santa.send_json(&json!({"type": "ping"})).await;
+        santa.assert_receive_json(&json!({"type": "pong"})).await;
+
+        santa
+            .send_json(&json!({"type": "dm", "to": "rudolph", "message": "hi"}))
+            .await;
+        santa
+            .assert_receive_json(&json!({
+                "type": "error",
+                "code": "unknown_recipient",
+                "to": "rudolph",
+                "detail": "rudolph is not in this room"
+            }))
+            .await;
+
+        // direct messages stay out of the room's history
+        server
+            .get("/19/rooms/9/messages")
+            .await
+            .assert_json_contains(&json!({"messages": [{"message": "hi all"}]}));
+        assert_eq!(
+            server.get("/19/rooms/9/messages").await.json::<serde_json::Value>()["messages"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn ping_game() {
+        let server = server().await;
+        let mut ws = server.get_websocket("/19/ws/ping").await.into_websocket().await;
+        ws.send_text("ping").await;
+        ws.send_text("serve").await;
+        ws.send_text("ping").await;
+        // the ping before the serve went unanswered
+        ws.assert_receive_text("pong").await;
+    }
+
+    #[tokio::test]
+    async fn heartbeat() {
+        let server = server_with(Config {
+            heartbeat_interval: std::time::Duration::from_millis(30),
+            max_missed_pongs: 2,
+            ..config()
+        })
+        .await;
+
+        server
+            .get("/19/admin/connections")
+            .await
+            .assert_status_unauthorized();
+        server
+            .get("/19/admin/connections")
+            .authorization_bearer("south-pole")
+            .await
+            .assert_status_unauthorized();
+
+        // a pong that comes back after the next ping still counts
+        let mut ping = server.get_websocket("/19/ws/ping").await.into_websocket().await;
+        let axum_test::WsMessage::Ping(payload) = ping.receive_message().await else {
+            panic!("expected a ping frame");
+        };
+        let axum_test::WsMessage::Ping(_) = ping.receive_message().await else {
+            panic!("expected a second ping frame");
+        };
+        ping.send_message(axum_test::WsMessage::Pong(payload)).await;
+        let mut rtt = serde_json::Value::Null;
+        for _ in 0..50 {
+            rtt = connections(&server).await.json::<serde_json::Value>()[0]["rtt_ms"].clone();
+            if rtt.is_number() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(rtt.is_number());
+        ping.close().await;
+
+        // a peer that never reads never pongs, and is dropped from the room
+        let _idle = connect(&server, "/19/ws/room/1/user/elf", "elf").await;
+        connections(&server)
+            .await
+            .assert_json_contains(&json!([{"socket": "room", "room": 1, "user": "elf"}]));
+        for _ in 0..50 {
+            if server.get("/19/rooms/1/members").await.json::<Vec<String>>().is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        server.get("/19/rooms/1/members").await.assert_json(&json!([]));
+        connections(&server).await.assert_json(&json!([]));
+    }
+
+    #[tokio::test]
+    async fn expired_session() {
+        let server = server_with(Config {
+            token_ttl: std::time::Duration::ZERO,
+            ..config()
+        })
+        .await;
+        let token = login(&server, "elf").await;
+        server
+            .post("/19/rooms/1/messages")
+            .authorization_bearer(token)
+            .json(&json!({"message": "too late"}))
+            .expect_failure()
+            .await
+            .assert_json_contains(&json!({"status": 401, "detail": "session token expired"}));
+    }
+
+    async fn login(server: &TestServer, user: &str) -> String {
+        let issued = server
+            .post("/19/login")
+            .json(&json!({"user": user, "password": PASSWORD}))
+            .await
+            .json::<serde_json::Value>();
+        issued["token"].as_str().unwrap().to_string()
+    }
+
+    async fn connect(server: &TestServer, path: &str, user: &str) -> TestWebSocket {
+        let token = login(server, user).await;
+        let sep = if path.contains('?') { '&' } else { '?' };
+        server
+            .get_websocket(&format!("{path}{sep}token={token}"))
+            .await
+            .into_websocket()
+            .await
+    }
+
+    /// Compares the fields of `expected`, and checks the server assigned an id
+    /// and a timestamp.
+    fn assert_chat(actual: &serde_json::Value, expected: &serde_json::Value) {
+        for (key, value) in expected.as_object().unwrap() {
+            assert_eq!(&actual[key], value, "{key} of {actual}");
+        }
+        assert!(actual["id"].as_str().unwrap().parse::<ulid::Ulid>().is_ok());
+        assert!(actual["sent_at"].as_str().unwrap().ends_with('Z'));
+    }
+
+    async fn assert_receive_chat(ws: &mut TestWebSocket, expected: &serde_json::Value) {
+        assert_chat(&ws.receive_json().await, expected);
+    }
+
+    /// Reads the next `data:` payload off an SSE response.
+    async fn next_sse(response: &mut reqwest::Response, buf: &mut String) -> serde_json::Value {
+        loop {
+            if let Some(end) = buf.find("\n\n") {
+                let event = buf[..end].to_string();
+                buf.drain(..end + 2);
+                if let Some(data) = event.lines().find_map(|l| l.strip_prefix("data: ")) {
+                    return serde_json::from_str(data).unwrap();
                 }
+                continue;
             }
+            let chunk = response.chunk().await.unwrap().expect("stream ended");
+            buf.push_str(std::str::from_utf8(&chunk).unwrap());
         }
     }
 
-    recv_task.abort();
+    /// Views are counted right after the socket write, poll briefly for them.
+    async fn assert_views(server: &TestServer, path: &str, expected: &str) {
+        for _ in 0..50 {
+            if server.get(path).await.text() == expected {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        server.get(path).await.assert_text(expected);
+    }
+
+    #[tokio::test]
+    async fn history_pages() {
+        let server = server().await;
+        let mut santa = connect(&server, "/19/ws/room/2/user/santa", "santa").await;
+        let _ = santa.receive_text().await;
+        for i in 0..3 {
+            santa.send_json(&json!({"message": i.to_string()})).await;
+            let _ = santa.receive_text().await;
+        }
+
+        let first = server
+            .get("/19/rooms/2/messages?limit=2")
+            .await
+            .json::<serde_json::Value>();
+        let messages = first["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["message"], "0");
+        assert_eq!(messages[1]["message"], "1");
+
+        let next = first["next"].as_str().unwrap();
+        let second = server
+            .get(&format!("/19/rooms/2/messages?limit=2&after={next}"))
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(second["messages"][0]["message"], "2");
+        assert_eq!(second["next"], json!(null));
+
+        server
+            .get("/19/rooms/2/messages?after=nope")
+            .await
+            .assert_status_bad_request();
+    }
 }
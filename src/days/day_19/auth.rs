@@ -0,0 +1,258 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query, State},
+    http::{header, request::Parts},
+    Json,
+};
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::Day19State;
+use crate::error::{AppError, JsonBody};
+
+const MAX_NAME: usize = 32;
+
+/// What a session token vouches for, signed as `base64(claims).base64(mac)`.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Claims {
+    user: String,
+    /// Unix seconds after which the token is refused.
+    exp: u64,
+}
+
+fn mac(secret: &[u8], payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("hmac takes keys of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+fn issue(secret: &[u8], user: &str, exp: u64) -> String {
+    let claims = serde_json::to_vec(&Claims {
+        user: user.to_string(),
+        exp,
+    })
+    .expect("claims serialize");
+    let payload = BASE64_URL_SAFE_NO_PAD.encode(claims);
+    let signature = BASE64_URL_SAFE_NO_PAD.encode(mac(secret, &payload).finalize().into_bytes());
+    format!("{payload}.{signature}")
+}
+
+/// The user `token` was issued to, if it's genuine and not expired at `now`.
+fn verify(secret: &[u8], token: &str, now: u64) -> Result<String, AppError> {
+    let malformed = || AppError::Unauthorized(String::from("malformed session token"));
+
+    let (payload, signature) = token.split_once('.').ok_or_else(malformed)?;
+    let signature = BASE64_URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| malformed())?;
+    // verify_slice compares in constant time
+    mac(secret, payload)
+        .verify_slice(&signature)
+        .map_err(|_| AppError::Unauthorized(String::from("invalid session token")))?;
+
+    let claims = BASE64_URL_SAFE_NO_PAD
+        .decode(payload)
+        .ok()
+        .and_then(|claims| serde_json::from_slice::<Claims>(&claims).ok())
+        .ok_or_else(malformed)?;
+    if claims.exp <= now {
+        return Err(AppError::Unauthorized(String::from(
+            "session token expired",
+        )));
+    }
+    Ok(claims.user)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct Login {
+    user: String,
+    password: String,
+}
+
+#[derive(serde::Serialize)]
+pub(super) struct Issued {
+    token: String,
+    /// Unix seconds.
+    expires_at: u64,
+}
+
+pub(super) async fn login(
+    State(state): State<Day19State>,
+    JsonBody(login): JsonBody<Login>,
+) -> Result<Json<Issued>, AppError> {
+    let user = login.user.trim();
+    if user.is_empty() || user.chars().count() > MAX_NAME {
+        return Err(AppError::bad_request(format!(
+            "user must be 1 to {MAX_NAME} characters"
+        )));
+    }
+    // compared as macs, in constant time, and unknown users take as long
+    let secret = &state.config.token_secret;
+    let known = state.config.passwords.get(user);
+    let expected = mac(secret, known.map_or("", String::as_str)).finalize();
+    let matches = mac(secret, &login.password)
+        .verify_slice(&expected.into_bytes())
+        .is_ok();
+    if !(matches && known.is_some()) {
+        return Err(AppError::Unauthorized(String::from(
+            "unknown user or wrong password",
+        )));
+    }
+    let expires_at = now() + state.config.token_ttl.as_secs();
+    Ok(Json(Issued {
+        token: issue(&state.config.token_secret, user, expires_at),
+        expires_at,
+    }))
+}
+
+#[derive(serde::Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// A verified session. The token is taken from `?token=`, an
+/// `Authorization: Bearer` header or, for browsers that can't set headers on
+/// a websocket handshake, one of the offered `Sec-WebSocket-Protocol`s.
+pub(super) struct Session {
+    pub(super) user: String,
+    /// The subprotocol the token came in, the handshake has to echo it back.
+    pub(super) protocol: Option<String>,
+}
+
+impl Session {
+    /// Refuses a session used to speak for someone else.
+    pub(super) fn require(&self, user: &str) -> Result<(), AppError> {
+        if self.user == user {
+            Ok(())
+        } else {
+            Err(AppError::Unauthorized(format!(
+                "session belongs to {}, not {user}",
+                self.user
+            )))
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Day19State> for Session {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Day19State,
+    ) -> Result<Self, Self::Rejection> {
+        Session::find(parts, state)?
+            .ok_or_else(|| AppError::Unauthorized(String::from("missing session token")))
+    }
+}
+
+/// The operator, holding `DAY19_ADMIN_KEY` as a bearer token.
+pub(super) struct Admin;
+
+#[async_trait]
+impl FromRequestParts<Day19State> for Admin {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Day19State,
+    ) -> Result<Self, Self::Rejection> {
+        let denied = || AppError::Unauthorized(String::from("admin key required"));
+        let key = state.config.admin_key.as_deref().ok_or_else(denied)?;
+        let bearer = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or_else(denied)?;
+        // compared as macs, in constant time
+        let secret = &state.config.token_secret;
+        mac(secret, bearer)
+            .verify_slice(&mac(secret, key).finalize().into_bytes())
+            .map(|()| Admin)
+            .map_err(|_| denied())
+    }
+}
+
+impl Session {
+    /// `None` if no token was offered, an error if one was but isn't valid.
+    fn find(parts: &Parts, state: &Day19State) -> Result<Option<Session>, AppError> {
+        let query = Query::<TokenQuery>::try_from_uri(&parts.uri)
+            .ok()
+            .and_then(|q| q.0.token);
+        let bearer = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::to_string);
+        let protocols = parts
+            .headers
+            .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|p| p.trim().to_string());
+
+        let candidates = query
+            .into_iter()
+            .chain(bearer)
+            .map(|token| (token, false))
+            .chain(protocols.map(|protocol| (protocol, true)));
+
+        let now = now();
+        let mut error = None;
+        for (token, is_protocol) in candidates {
+            match verify(&state.config.token_secret, &token, now) {
+                Ok(user) => {
+                    return Ok(Some(Session {
+                        user,
+                        protocol: is_protocol.then_some(token),
+                    }))
+                }
+                // other subprotocols are allowed alongside the token
+                Err(_) if is_protocol => {}
+                Err(e) => error = error.or(Some(e)),
+            }
+        }
+        error.map_or(Ok(None), Err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{issue, verify};
+
+    const SECRET: &[u8] = b"north pole";
+
+    #[test]
+    fn round_trip_and_expiry() {
+        let token = issue(SECRET, "santa", 1000);
+        assert_eq!(verify(SECRET, &token, 999).unwrap(), "santa");
+        assert!(verify(SECRET, &token, 1000).is_err());
+    }
+
+    #[test]
+    fn tampering() {
+        let token = issue(SECRET, "elf", 1000);
+        let (_, signature) = token.split_once('.').unwrap();
+
+        // a forged payload with the elf's signature
+        let forged = issue(SECRET, "santa", 1000);
+        let (payload, _) = forged.split_once('.').unwrap();
+        assert!(verify(SECRET, &format!("{payload}.{signature}"), 0).is_err());
+
+        assert!(verify(b"another secret", &token, 0).is_err());
+        assert!(verify(SECRET, "not a token", 0).is_err());
+        assert!(verify(SECRET, &token[1..], 0).is_err());
+    }
+}
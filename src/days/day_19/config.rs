@@ -0,0 +1,150 @@
+use std::{collections::HashMap, num::NonZeroU32, time::Duration};
+
+use governor::Quota;
+
+const DEFAULT_CAPACITY: usize = 128;
+const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_MAX_CHARS: usize = 128;
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(15);
+const DEFAULT_MAX_MISSED_PONGS: u32 = 3;
+
+/// Chat room and session settings, fixed for the lifetime of the server.
+#[derive(Clone)]
+pub(super) struct Config {
+    /// Broadcast slots of a room not listed in `room_capacity`.
+    pub(super) capacity: usize,
+    pub(super) room_capacity: HashMap<u64, usize>,
+    /// How long a write to a client may block before it's disconnected.
+    pub(super) write_timeout: Duration,
+    /// HMAC key for session tokens.
+    pub(super) token_secret: Vec<u8>,
+    pub(super) token_ttl: Duration,
+    /// Who may log in, and with which password. Nobody by default.
+    pub(super) passwords: HashMap<String, String>,
+    /// Bearer key of the admin endpoints, which are closed without one.
+    pub(super) admin_key: Option<String>,
+    /// Messages each user may send per room.
+    pub(super) rate: Quota,
+    pub(super) banned_words: Vec<String>,
+    /// Longest message, in characters rather than bytes.
+    pub(super) max_chars: usize,
+    /// How often every websocket is sent a Ping frame.
+    pub(super) heartbeat_interval: Duration,
+    /// Unanswered pings after which a socket is closed.
+    pub(super) max_missed_pongs: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            capacity: DEFAULT_CAPACITY,
+            room_capacity: HashMap::new(),
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            // tokens don't survive a restart unless a secret is configured
+            token_secret: rand::random::<[u8; 32]>().to_vec(),
+            token_ttl: DEFAULT_TOKEN_TTL,
+            passwords: HashMap::new(),
+            admin_key: None,
+            rate: quota(2, 10),
+            banned_words: Vec::new(),
+            max_chars: DEFAULT_MAX_CHARS,
+            heartbeat_interval: DEFAULT_HEARTBEAT,
+            max_missed_pongs: DEFAULT_MAX_MISSED_PONGS,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config from the environment:
+    ///
+    /// - `DAY19_CAPACITY`: broadcast slots per room, defaults to 128
+    /// - `DAY19_ROOM_CAPACITY`: per room overrides, as `room=slots,room=slots`
+    /// - `DAY19_WRITE_TIMEOUT_MS`: write timeout in milliseconds, defaults to 5000
+    /// - `DAY19_TOKEN_SECRET`: session token key, random per process by default
+    /// - `DAY19_TOKEN_TTL`: session token lifetime in seconds, defaults to an hour
+    /// - `DAY19_USERS`: who may log in, as `user:password,user:password`
+    /// - `DAY19_ADMIN_KEY`: bearer key of the admin endpoints, closed if unset
+    /// - `DAY19_RATE_PER_SEC` and `DAY19_RATE_BURST`: messages per user and room,
+    ///   default 2 a second with bursts of 10
+    /// - `DAY19_BANNED_WORDS`: comma separated, case insensitive
+    /// - `DAY19_MAX_CHARS`: longest message in characters, defaults to 128
+    /// - `DAY19_HEARTBEAT_MS`: interval between Ping frames, defaults to 15000
+    /// - `DAY19_MAX_MISSED_PONGS`: defaults to 3
+    pub(super) fn from_env() -> Self {
+        let var = |key: &str| std::env::var(key).ok();
+        let mut config = Config::default();
+
+        if let Some(capacity) = var("DAY19_CAPACITY").and_then(|v| v.parse().ok()) {
+            config.capacity = capacity;
+        }
+        for entry in var("DAY19_ROOM_CAPACITY").iter().flat_map(|v| v.split(',')) {
+            match entry.split_once('=').and_then(|(room, slots)| {
+                Some((room.trim().parse().ok()?, slots.trim().parse().ok()?))
+            }) {
+                Some((room, slots)) => {
+                    config.room_capacity.insert(room, slots);
+                }
+                None => tracing::warn!(entry, "ignoring malformed DAY19_ROOM_CAPACITY entry"),
+            }
+        }
+        if let Some(ms) = var("DAY19_WRITE_TIMEOUT_MS").and_then(|v| v.parse().ok()) {
+            config.write_timeout = Duration::from_millis(ms);
+        }
+        if let Some(secret) = var("DAY19_TOKEN_SECRET").filter(|s| !s.is_empty()) {
+            config.token_secret = secret.into_bytes();
+        }
+        if let Some(secs) = var("DAY19_TOKEN_TTL").and_then(|v| v.parse().ok()) {
+            config.token_ttl = Duration::from_secs(secs);
+        }
+        for entry in var("DAY19_USERS").iter().flat_map(|v| v.split(',')) {
+            match entry.split_once(':') {
+                Some((user, password)) if !user.trim().is_empty() => {
+                    config
+                        .passwords
+                        .insert(user.trim().to_string(), password.to_string());
+                }
+                _ => tracing::warn!("ignoring malformed DAY19_USERS entry"),
+            }
+        }
+        config.admin_key = var("DAY19_ADMIN_KEY").filter(|key| !key.is_empty());
+        let per_sec = var("DAY19_RATE_PER_SEC").and_then(|v| v.parse().ok());
+        let burst = var("DAY19_RATE_BURST").and_then(|v| v.parse().ok());
+        if per_sec.is_some() || burst.is_some() {
+            config.rate = quota(per_sec.unwrap_or(2), burst.unwrap_or(10));
+        }
+        if let Some(words) = var("DAY19_BANNED_WORDS") {
+            config.banned_words = words
+                .split(',')
+                .map(str::trim)
+                .filter(|w| !w.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(max) = var("DAY19_MAX_CHARS").and_then(|v| v.parse().ok()) {
+            config.max_chars = max;
+        }
+        if let Some(ms) = var("DAY19_HEARTBEAT_MS").and_then(|v| v.parse().ok()) {
+            config.heartbeat_interval = Duration::from_millis(ms);
+        }
+        if let Some(missed) = var("DAY19_MAX_MISSED_PONGS").and_then(|v| v.parse().ok()) {
+            config.max_missed_pongs = missed;
+        }
+        config
+    }
+
+    /// Broadcast slots for `room`, at least one since tokio rejects zero.
+    pub(super) fn capacity(&self, room: u64) -> usize {
+        self.room_capacity
+            .get(&room)
+            .copied()
+            .unwrap_or(self.capacity)
+            .max(1)
+    }
+}
+
+/// Governor wants non-zero rates, zeroes are bumped to one.
+fn quota(per_sec: u32, burst: u32) -> Quota {
+    let non_zero = |n: u32| NonZeroU32::new(n.max(1)).expect("at least one");
+    Quota::per_second(non_zero(per_sec)).allow_burst(non_zero(burst))
+}
@@ -0,0 +1,346 @@
+use std::{collections::VecDeque, sync::atomic::Ordering, time::Duration};
+
+use futures_util::{Sink, SinkExt};
+use tokio::{
+    select,
+    sync::{
+        broadcast::{error::RecvError, Receiver},
+        mpsc,
+    },
+};
+use ulid::Ulid;
+
+use super::{history, moderation::Rejection, rooms, Day19State, Event, Message, Presence};
+
+/// Undelivered notices a client may have queued before new ones are dropped.
+const MAX_NOTICES: usize = 16;
+
+/// What a client connected to a room receives, whatever the transport:
+/// the replayed history first, then the room's live events.
+pub(super) struct Feed {
+    state: Day19State,
+    room: u64,
+    user: String,
+    recv: Receiver<Event>,
+    backlog: VecDeque<Message>,
+    /// Newest replayed message, live copies of it and older ones are skipped.
+    replayed: Ulid,
+    notify: mpsc::Sender<Notice>,
+    notices: mpsc::Receiver<Notice>,
+    _membership: rooms::Membership,
+}
+
+/// Sent to a single client about its own connection.
+#[derive(serde::Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(super) enum Notice {
+    Lagged {
+        missed: u64,
+    },
+    /// Answers an application level `{"type": "ping"}`.
+    Pong,
+    Error {
+        #[serde(flatten)]
+        rejection: Rejection,
+        detail: String,
+    },
+}
+
+impl From<Rejection> for Notice {
+    fn from(rejection: Rejection) -> Self {
+        Notice::Error {
+            detail: rejection.to_string(),
+            rejection,
+        }
+    }
+}
+
+impl From<Notice> for Outgoing {
+    fn from(notice: Notice) -> Self {
+        Outgoing {
+            text: serde_json::to_string(&notice).unwrap_or_default(),
+            live_chat: false,
+        }
+    }
+}
+
+/// A serialized event ready for the wire.
+#[derive(Clone)]
+pub(super) struct Outgoing {
+    pub(super) text: String,
+    /// Live tweets count as views, replays and presence events don't.
+    live_chat: bool,
+}
+
+impl Feed {
+    pub(super) async fn open(
+        state: &Day19State,
+        room: u64,
+        user: &str,
+        replay: u32,
+    ) -> Option<Self> {
+        // subscribe before reading history so nothing falls in between
+        let (membership, recv) = rooms::join(state, room, user)?;
+
+        let backlog = if replay > 0 {
+            history::recent(&state.pool, room, replay)
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = %e, room, "failed to load chat history");
+                    Vec::new()
+                })
+                .into_iter()
+                .filter_map(|stored| {
+                    Some(Message::new(
+                        stored.id.parse().ok()?,
+                        stored.user,
+                        stored.message,
+                        None,
+                    ))
+                })
+                .collect()
+        } else {
+            VecDeque::new()
+        };
+
+        let (notify, notices) = mpsc::channel(MAX_NOTICES);
+        Some(Feed {
+            notify,
+            notices,
+            state: state.clone(),
+            room,
+            user: user.to_string(),
+            recv,
+            replayed: backlog.back().map_or(Ulid::nil(), |m: &Message| m.id),
+            backlog,
+            _membership: membership,
+        })
+    }
+
+    /// Waits for the next event, `None` once the room is gone.
+    pub(super) async fn next(&mut self) -> Option<Outgoing> {
+        if let Some(message) = self.backlog.pop_front() {
+            return Some(Outgoing {
+                text: serde_json::to_string(&message).ok()?,
+                live_chat: false,
+            });
+        }
+
+        loop {
+            let received = select! {
+                Some(notice) = self.notices.recv() => return Some(notice.into()),
+                received = self.recv.recv() => received,
+            };
+            let event = match received {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => return Some(self.resubscribe(skipped)),
+                Err(RecvError::Closed) => return None,
+            };
+            let live_chat = match &event {
+                Event::Chat(message)
+                    if message.id <= self.replayed || !message.is_for(&self.user) =>
+                {
+                    continue
+                }
+                Event::Chat(_) => true,
+                // nobody needs to be told they're typing
+                Event::Presence(Presence::Typing { user }) if *user == self.user => continue,
+                Event::Presence(_) => false,
+            };
+            if let Ok(text) = serde_json::to_string(&event) {
+                return Some(Outgoing { text, live_chat });
+            }
+        }
+    }
+
+    /// The client fell more than a channel's worth behind. Rather than
+    /// trickling out a stale backlog it's told how much it missed and picks
+    /// up again from the newest message.
+    fn resubscribe(&mut self, skipped: u64) -> Outgoing {
+        let missed = skipped + self.recv.len() as u64;
+        self.recv = self.recv.resubscribe();
+        tracing::info!(room = self.room, user = %self.user, missed, "chat client lagged");
+        Notice::Lagged { missed }.into()
+    }
+
+    /// Queues notices for this client alone, dropped if it isn't reading them.
+    pub(super) fn notifier(&self) -> mpsc::Sender<Notice> {
+        self.notify.clone()
+    }
+
+    /// Records that `outgoing` reached the client.
+    pub(super) fn delivered(&self, outgoing: &Outgoing) {
+        if !outgoing.live_chat {
+            return;
+        }
+        if let Ok(mut stats) = self.state.stats.lock() {
+            stats.delivered(self.room, &self.user, outgoing.text.len());
+        }
+        self.state.view_count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(super) enum Disconnect {
+    #[error("write blocked for more than {0:?}")]
+    Timeout(Duration),
+    #[error("client went away")]
+    Closed,
+}
+
+/// Writes the feed to `sink` until the room or the client goes away, giving
+/// up on a client whose writes block for longer than `write_timeout`.
+/// Frames on `control`, like heartbeat pings, are written as they come.
+pub(super) async fn forward<S, T>(
+    feed: &mut Feed,
+    mut sink: S,
+    mut control: mpsc::Receiver<T>,
+    write_timeout: Duration,
+) -> Result<(), Disconnect>
+where
+    S: Sink<T> + Unpin,
+    T: From<String>,
+{
+    loop {
+        let (frame, outgoing) = select! {
+            Some(frame) = control.recv() => (frame, None),
+            outgoing = feed.next() => match outgoing {
+                Some(outgoing) => (outgoing.text.clone().into(), Some(outgoing)),
+                None => return Ok(()),
+            },
+        };
+        match tokio::time::timeout(write_timeout, sink.send(frame)).await {
+            Ok(Ok(())) => {
+                if let Some(outgoing) = outgoing {
+                    feed.delivered(&outgoing);
+                }
+            }
+            Ok(Err(_)) => return Err(Disconnect::Closed),
+            Err(_) => return Err(Disconnect::Timeout(write_timeout)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        pin::Pin,
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use futures_util::Sink;
+    use sqlx::SqlitePool;
+    use ulid::Ulid;
+
+    use super::{
+        super::{config::Config, rooms, Day19State, Event, Message},
+        forward, Disconnect, Feed,
+    };
+
+    fn state(capacity: usize) -> Day19State {
+        Day19State::new(
+            SqlitePool::connect_lazy("sqlite::memory:").unwrap(),
+            Config {
+                capacity,
+                room_capacity: HashMap::from([(2, 64)]),
+                write_timeout: Duration::from_millis(50),
+                ..Config::default()
+            },
+        )
+    }
+
+    fn tweet(state: &Day19State, room: u64, message: &str) {
+        let sender = rooms::sender(&state.sockets.lock().unwrap(), room).unwrap();
+        let sent = sender.send(Event::Chat(Message::new(
+            Ulid::new(),
+            String::from("santa"),
+            message.to_string(),
+            None,
+        )));
+        assert!(sent.is_ok());
+    }
+
+    #[tokio::test]
+    async fn slow_reader_is_told_what_it_missed() {
+        let state = state(4);
+        let mut feed = Feed::open(&state, 1, "elf", 0).await.unwrap();
+        // the join event plus ten tweets, nobody reading
+        for i in 0..10 {
+            tweet(&state, 1, &i.to_string());
+        }
+
+        let notice = feed.next().await.unwrap();
+        assert_eq!(notice.text, r#"{"type":"lagged","missed":11}"#);
+        feed.delivered(&notice);
+
+        tweet(&state, 1, "caught up");
+        let live = feed.next().await.unwrap();
+        assert!(live
+            .text
+            .starts_with(r#"{"user":"santa","message":"caught up","type":"chat","#));
+        feed.delivered(&live);
+        assert_eq!(
+            state.view_count.load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn room_capacity_overrides() {
+        let state = state(4);
+        let mut feed = Feed::open(&state, 2, "elf", 0).await.unwrap();
+        for i in 0..10 {
+            tweet(&state, 2, &i.to_string());
+        }
+        assert_eq!(
+            feed.next().await.unwrap().text,
+            r#"{"type":"join","user":"elf"}"#
+        );
+        assert!(feed
+            .next()
+            .await
+            .unwrap()
+            .text
+            .starts_with(r#"{"user":"santa","message":"0","#));
+    }
+
+    /// A client whose socket buffer is full and never drains.
+    struct Stalled;
+
+    impl Sink<String> for Stalled {
+        type Error = ();
+
+        fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            Poll::Pending
+        }
+
+        fn start_send(self: Pin<&mut Self>, _: String) -> Result<(), ()> {
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn blocked_writes_disconnect() {
+        let state = state(4);
+        let mut feed = Feed::open(&state, 3, "elf", 0).await.unwrap();
+        tweet(&state, 3, "anyone?");
+
+        let (_control, control_recv) = tokio::sync::mpsc::channel(1);
+        let result = forward(&mut feed, Stalled, control_recv, Duration::from_millis(50)).await;
+        assert!(matches!(result, Err(Disconnect::Timeout(_))));
+        assert_eq!(
+            state.view_count.load(std::sync::atomic::Ordering::Relaxed),
+            0
+        );
+    }
+}
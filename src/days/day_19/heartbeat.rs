@@ -0,0 +1,155 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use axum::{extract::State, Json};
+use tokio::time::{interval_at, Instant, Interval, MissedTickBehavior};
+
+use super::{auth::Admin, Day19State};
+
+/// Every open Day 19 websocket, for the admin endpoint.
+#[derive(Default)]
+pub(super) struct Connections {
+    next_id: AtomicU64,
+    live: Mutex<HashMap<u64, Info>>,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub(super) struct Info {
+    id: u64,
+    #[serde(flatten)]
+    socket: Socket,
+    /// Round trip of the last answered ping.
+    rtt_ms: Option<f64>,
+    missed_pongs: u32,
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(tag = "socket", rename_all = "lowercase")]
+pub(super) enum Socket {
+    Ping,
+    Room { room: u64, user: String },
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("peer missed {0} pongs")]
+pub(super) struct Dead(u32);
+
+/// Pings one socket on an interval and tracks its pongs. The socket is
+/// listed under `/admin/connections` for as long as this lives.
+pub(super) struct Heartbeat {
+    state: Day19State,
+    id: u64,
+    interval: Interval,
+    max_missed: u32,
+    missed: u32,
+    seq: u64,
+    /// Pings sent since the last pong, oldest first. A slow peer may answer
+    /// one after the next has gone out.
+    outstanding: VecDeque<(u64, Instant)>,
+}
+
+impl Heartbeat {
+    pub(super) fn new(state: &Day19State, socket: Socket) -> Self {
+        let period = state.config.heartbeat_interval.max(Duration::from_millis(1));
+        let mut interval = interval_at(Instant::now() + period, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let id = state.connections.next_id.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut live) = state.connections.live.lock() {
+            live.insert(
+                id,
+                Info {
+                    id,
+                    socket,
+                    rtt_ms: None,
+                    missed_pongs: 0,
+                },
+            );
+        }
+
+        Heartbeat {
+            state: state.clone(),
+            id,
+            interval,
+            max_missed: state.config.max_missed_pongs,
+            missed: 0,
+            seq: 0,
+            outstanding: VecDeque::new(),
+        }
+    }
+
+    /// Waits for the next beat and returns the payload of the ping to send,
+    /// or gives up on the peer if it hasn't answered any of the last pings.
+    pub(super) async fn tick(&mut self) -> Result<Vec<u8>, Dead> {
+        self.interval.tick().await;
+        if !self.outstanding.is_empty() {
+            self.missed += 1;
+            self.update(|info| info.missed_pongs += 1);
+            if self.missed >= self.max_missed {
+                return Err(Dead(self.missed));
+            }
+        }
+        self.seq += 1;
+        self.outstanding.push_back((self.seq, Instant::now()));
+        Ok(self.seq.to_be_bytes().to_vec())
+    }
+
+    /// Records a pong to any outstanding ping, which answers the older ones
+    /// as well. Unsolicited pongs are ignored.
+    pub(super) fn pong(&mut self, payload: &[u8]) {
+        let Some(answered) = self
+            .outstanding
+            .iter()
+            .position(|(seq, _)| payload == seq.to_be_bytes())
+        else {
+            return;
+        };
+        let (_, sent) = self.outstanding[answered];
+        self.outstanding.drain(..=answered);
+        self.missed = 0;
+        let rtt = sent.elapsed().as_secs_f64() * 1000.0;
+        self.update(|info| {
+            info.rtt_ms = Some(rtt);
+            info.missed_pongs = 0;
+        });
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Info)) {
+        if let Some(info) = self
+            .state
+            .connections
+            .live
+            .lock()
+            .ok()
+            .as_mut()
+            .and_then(|live| live.get_mut(&self.id))
+        {
+            f(info);
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        if let Ok(mut live) = self.state.connections.live.lock() {
+            live.remove(&self.id);
+        }
+    }
+}
+
+pub(super) async fn connections(_: Admin, State(state): State<Day19State>) -> Json<Vec<Info>> {
+    let mut connections: Vec<Info> = state
+        .connections
+        .live
+        .lock()
+        .map(|live| live.values().cloned().collect())
+        .unwrap_or_default();
+    connections.sort_by_key(|info| info.id);
+    Json(connections)
+}
@@ -0,0 +1,86 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use sqlx::SqlitePool;
+use ulid::Ulid;
+
+use super::{Day19State, Message};
+use crate::error::AppError;
+
+const MAX_PAGE: u32 = 200;
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub(super) struct StoredMessage {
+    pub(super) id: String,
+    pub(super) user: String,
+    pub(super) message: String,
+}
+
+pub(super) async fn store(pool: &SqlitePool, room: u64, message: &Message) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO chat_messages (id, room_id, user, message) VALUES (?, ?, ?, ?)")
+        .bind(message.id.to_string())
+        .bind(room as i64)
+        .bind(&message.user)
+        .bind(&message.message)
+        .execute(pool)
+        .await
+        .map(|_| ())
+}
+
+/// The last `limit` messages of `room`, oldest first.
+pub(super) async fn recent(
+    pool: &SqlitePool,
+    room: u64,
+    limit: u32,
+) -> sqlx::Result<Vec<StoredMessage>> {
+    let mut messages = sqlx::query_as::<_, StoredMessage>(
+        "SELECT id, user, message FROM chat_messages WHERE room_id = ? ORDER BY id DESC LIMIT ?",
+    )
+    .bind(room as i64)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    messages.reverse();
+    Ok(messages)
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct PageQuery {
+    after: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(serde::Serialize)]
+pub(super) struct Page {
+    messages: Vec<StoredMessage>,
+    /// Pass as `after` to fetch the following page, `None` on the last page.
+    next: Option<String>,
+}
+
+pub(super) async fn messages(
+    Path(id): Path<u64>,
+    Query(q): Query<PageQuery>,
+    State(state): State<Day19State>,
+) -> Result<Json<Page>, AppError> {
+    let after = match q.after {
+        Some(after) => after.parse::<Ulid>()?.to_string(),
+        None => String::new(),
+    };
+    let limit = q.limit.unwrap_or(50).clamp(1, MAX_PAGE);
+
+    let messages = sqlx::query_as::<_, StoredMessage>(
+        "SELECT id, user, message FROM chat_messages WHERE room_id = ? AND id > ? ORDER BY id LIMIT ?",
+    )
+    .bind(id as i64)
+    .bind(after)
+    .bind(limit)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let next = match messages.last() {
+        Some(last) if messages.len() == limit as usize => Some(last.id.clone()),
+        _ => None,
+    };
+    Ok(Json(Page { messages, next }))
+}
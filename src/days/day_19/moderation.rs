@@ -0,0 +1,158 @@
+use governor::{
+    clock::{Clock, DefaultClock},
+    DefaultKeyedRateLimiter, RateLimiter,
+};
+
+use super::config::Config;
+use crate::error::AppError;
+
+/// Why a message wasn't published, sent back to its author as an error frame.
+#[derive(Debug, thiserror::Error, serde::Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub(super) enum Rejection {
+    #[error("message is not valid chat json")]
+    Malformed,
+    #[error("message is longer than {max_chars} characters")]
+    TooLong { max_chars: usize },
+    #[error("message contains a banned word")]
+    BannedWord { word: String },
+    #[error("too many messages, retry in {retry_after_ms}ms")]
+    RateLimited { retry_after_ms: u64 },
+    #[error("{to} is not in this room")]
+    UnknownRecipient { to: String },
+    #[error("chat is unavailable")]
+    Unavailable,
+}
+
+impl From<Rejection> for AppError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::RateLimited { .. } => AppError::RateLimited(rejection.to_string()),
+            Rejection::Unavailable => AppError::Internal(rejection.to_string()),
+            _ => AppError::bad_request(rejection),
+        }
+    }
+}
+
+/// Limiter keys kept before [`Moderator::check`] forgets idle ones itself,
+/// for users who only ever post over HTTP and so never leave a room.
+const MAX_KEYS: usize = 10_000;
+
+/// Per (room, user) flood control and content rules, shared by every transport.
+pub(super) struct Moderator {
+    limiter: DefaultKeyedRateLimiter<(u64, String)>,
+    /// Lowercase, matched against whole words.
+    banned_words: Vec<String>,
+    max_chars: usize,
+}
+
+impl Moderator {
+    pub(super) fn new(config: &Config) -> Self {
+        Moderator {
+            limiter: RateLimiter::keyed(config.rate),
+            banned_words: config
+                .banned_words
+                .iter()
+                .map(|word| word.to_lowercase())
+                .collect(),
+            max_chars: config.max_chars,
+        }
+    }
+
+    /// Content is checked first, so rejected messages don't use up the quota.
+    pub(super) fn check(&self, room: u64, user: &str, message: &str) -> Result<(), Rejection> {
+        if message.chars().count() > self.max_chars {
+            return Err(Rejection::TooLong {
+                max_chars: self.max_chars,
+            });
+        }
+
+        let banned = message
+            .split(|c: char| !c.is_alphanumeric())
+            .map(str::to_lowercase)
+            .find(|word| self.banned_words.contains(word));
+        if let Some(word) = banned {
+            return Err(Rejection::BannedWord { word });
+        }
+
+        if self.limiter.len() > MAX_KEYS {
+            self.forget_idle();
+        }
+        self.limiter
+            .check_key(&(room, user.to_string()))
+            .map_err(|not_until| Rejection::RateLimited {
+                retry_after_ms: not_until
+                    .wait_time_from(DefaultClock::default().now())
+                    .as_millis() as u64,
+            })
+    }
+
+    /// Drops the quotas of users that have fully recovered, which a fresh
+    /// key would match anyway.
+    pub(super) fn forget_idle(&self) {
+        self.limiter.retain_recent();
+        self.limiter.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{num::NonZeroU32, time::Duration};
+
+    use governor::Quota;
+
+    use super::{super::config::Config, Moderator, Rejection};
+
+    fn moderator() -> Moderator {
+        Moderator::new(&Config {
+            rate: Quota::per_hour(NonZeroU32::new(1).unwrap())
+                .allow_burst(NonZeroU32::new(2).unwrap()),
+            banned_words: vec![String::from("Grinch")],
+            max_chars: 6,
+            ..Config::default()
+        })
+    }
+
+    #[test]
+    fn content() {
+        let moderator = moderator();
+        // six characters, twelve bytes
+        assert!(moderator.check(1, "elf", "éééééé").is_ok());
+        assert!(matches!(
+            moderator.check(1, "elf", "ééééééé"),
+            Err(Rejection::TooLong { max_chars: 6 })
+        ));
+        assert!(matches!(
+            moderator.check(1, "elf", "GRINCH"),
+            Err(Rejection::BannedWord { word }) if word == "grinch"
+        ));
+        // only whole words
+        assert!(moderator.check(2, "elf", "grin").is_ok());
+    }
+
+    #[test]
+    fn rate_is_per_room_and_user() {
+        let moderator = moderator();
+        assert!(moderator.check(1, "elf", "hi").is_ok());
+        assert!(moderator.check(1, "elf", "hi").is_ok());
+        assert!(matches!(
+            moderator.check(1, "elf", "hi"),
+            Err(Rejection::RateLimited { retry_after_ms }) if retry_after_ms > 0
+        ));
+        assert!(moderator.check(2, "elf", "hi").is_ok());
+        assert!(moderator.check(1, "santa", "hi").is_ok());
+    }
+
+    #[test]
+    fn idle_quotas_are_forgotten() {
+        let moderator = Moderator::new(&Config {
+            rate: Quota::with_period(Duration::from_millis(1)).unwrap(),
+            ..Config::default()
+        });
+        assert!(moderator.check(1, "elf", "hi").is_ok());
+        assert_eq!(moderator.limiter.len(), 1);
+        std::thread::sleep(Duration::from_millis(10));
+        moderator.forget_idle();
+        assert!(moderator.limiter.is_empty());
+    }
+}
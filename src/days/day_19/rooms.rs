@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use tokio::sync::broadcast::{Receiver, Sender};
+
+use super::{Day19State, Event, Presence};
+
+pub(super) struct Room {
+    sender: Sender<Event>,
+    /// Connected users and how many sockets each has open in the room.
+    members: HashMap<String, usize>,
+}
+
+/// A user's seat in a room, leaving it when dropped so a dead connection
+/// can't linger in the member list.
+pub(super) struct Membership {
+    state: Day19State,
+    room: u64,
+    user: String,
+}
+
+impl Drop for Membership {
+    fn drop(&mut self) {
+        if let Ok(mut stats) = self.state.stats.lock() {
+            stats.disconnected(self.room, &self.user);
+        }
+        let Ok(mut rooms) = self.state.sockets.lock() else {
+            return;
+        };
+        let Some(room) = rooms.get_mut(&self.room) else {
+            return;
+        };
+        if let Some(count) = room.members.get_mut(&self.user) {
+            *count -= 1;
+            if *count == 0 {
+                room.members.remove(&self.user);
+                let _ = room.sender.send(Event::Presence(Presence::Leave {
+                    user: self.user.clone(),
+                }));
+            }
+        }
+        if room.members.is_empty() {
+            rooms.remove(&self.room);
+            drop(rooms);
+            self.state.moderator.forget_idle();
+        }
+    }
+}
+
+/// Adds `user` to `room`, creating the room if needed. The receiver is
+/// subscribed before the join is announced, so it sees its own join event.
+pub(super) fn join(
+    state: &Day19State,
+    room: u64,
+    user: &str,
+) -> Option<(Membership, Receiver<Event>)> {
+    state.stats.lock().ok()?.connected(room, user);
+    let mut rooms = state.sockets.lock().ok()?;
+    let room_state = rooms.entry(room).or_insert_with(|| Room {
+        sender: Sender::new(state.config.capacity(room)),
+        members: HashMap::new(),
+    });
+
+    let recv = room_state.sender.subscribe();
+    let count = room_state.members.entry(user.to_string()).or_default();
+    *count += 1;
+    if *count == 1 {
+        let _ = room_state.sender.send(Event::Presence(Presence::Join {
+            user: user.to_string(),
+        }));
+    }
+
+    Some((
+        Membership {
+            state: state.clone(),
+            room,
+            user: user.to_string(),
+        },
+        recv,
+    ))
+}
+
+/// The room's broadcast channel, `None` when nobody is connected to it.
+pub(super) fn sender(rooms: &HashMap<u64, Room>, room: u64) -> Option<Sender<Event>> {
+    rooms.get(&room).map(|room| room.sender.clone())
+}
+
+/// Sends `event` to everyone in `room`, a no-op for empty rooms.
+pub(super) fn broadcast(state: &Day19State, room: u64, event: Event) {
+    let sender = state
+        .sockets
+        .lock()
+        .ok()
+        .and_then(|rooms| sender(&rooms, room));
+    if let Some(sender) = sender {
+        let _ = sender.send(event);
+    }
+}
+
+pub(super) fn is_member(state: &Day19State, room: u64, user: &str) -> bool {
+    state
+        .sockets
+        .lock()
+        .is_ok_and(|rooms| rooms.get(&room).is_some_and(|room| room.members.contains_key(user)))
+}
+
+pub(super) async fn members(
+    Path(id): Path<u64>,
+    State(state): State<Day19State>,
+) -> Json<Vec<String>> {
+    let mut members = state
+        .sockets
+        .lock()
+        .ok()
+        .and_then(|rooms| rooms.get(&id).map(|room| room.members.keys().cloned().collect()))
+        .unwrap_or_else(Vec::new);
+    members.sort();
+    Json(members)
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::SqlitePool;
+
+    use super::{
+        super::{config::Config, Day19State},
+        join,
+    };
+
+    #[tokio::test]
+    async fn empty_rooms_are_dropped() {
+        let state = Day19State::new(
+            SqlitePool::connect_lazy("sqlite::memory:").unwrap(),
+            Config::default(),
+        );
+        let (first, _) = join(&state, 1, "santa").unwrap();
+        let (second, _) = join(&state, 1, "santa").unwrap();
+        let (elf, _) = join(&state, 2, "elf").unwrap();
+
+        drop(first);
+        assert!(state.sockets.lock().unwrap().contains_key(&1));
+        drop(second);
+        assert!(!state.sockets.lock().unwrap().contains_key(&1));
+        drop(elf);
+        assert!(state.sockets.lock().unwrap().is_empty());
+    }
+}
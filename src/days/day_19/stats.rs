@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+
+use super::Day19State;
+
+#[derive(Clone, Copy, Default, serde::Serialize)]
+pub(super) struct Counters {
+    messages_sent: u64,
+    messages_delivered: u64,
+    bytes_delivered: u64,
+}
+
+#[derive(Default)]
+struct Entry {
+    counters: Counters,
+    /// Open connections, the entry going away with the last one.
+    connections: usize,
+}
+
+/// Per-room and per-user traffic, next to the global view counter. Only
+/// connected rooms and users are tracked, so clients can't grow the maps by
+/// making up names.
+#[derive(Default)]
+pub(super) struct Stats {
+    rooms: HashMap<u64, Entry>,
+    users: HashMap<String, Entry>,
+}
+
+impl Stats {
+    pub(super) fn connected(&mut self, room: u64, user: &str) {
+        self.rooms.entry(room).or_default().connections += 1;
+        self.users.entry(user.to_string()).or_default().connections += 1;
+    }
+
+    pub(super) fn disconnected(&mut self, room: u64, user: &str) {
+        if let Some(entry) = self.rooms.get_mut(&room) {
+            entry.connections -= 1;
+            if entry.connections == 0 {
+                self.rooms.remove(&room);
+            }
+        }
+        if let Some(entry) = self.users.get_mut(user) {
+            entry.connections -= 1;
+            if entry.connections == 0 {
+                self.users.remove(user);
+            }
+        }
+    }
+
+    pub(super) fn sent(&mut self, room: u64, user: &str) {
+        for counters in self.counters(room, user) {
+            counters.messages_sent += 1;
+        }
+    }
+
+    /// A message of `bytes` was delivered to `user` in `room`.
+    pub(super) fn delivered(&mut self, room: u64, user: &str, bytes: usize) {
+        for counters in self.counters(room, user) {
+            counters.messages_delivered += 1;
+            counters.bytes_delivered += bytes as u64;
+        }
+    }
+
+    pub(super) fn reset_room(&mut self, room: u64) {
+        if let Some(entry) = self.rooms.get_mut(&room) {
+            entry.counters = Counters::default();
+        }
+    }
+
+    pub(super) fn clear(&mut self) {
+        for entry in self.rooms.values_mut().chain(self.users.values_mut()) {
+            entry.counters = Counters::default();
+        }
+    }
+
+    /// The counters of `room` and `user`, those of them that are connected.
+    fn counters(&mut self, room: u64, user: &str) -> impl Iterator<Item = &mut Counters> {
+        [self.rooms.get_mut(&room), self.users.get_mut(user)]
+            .into_iter()
+            .flatten()
+            .map(|entry| &mut entry.counters)
+    }
+}
+
+pub(super) async fn room_stats(
+    Path(id): Path<u64>,
+    State(state): State<Day19State>,
+) -> Json<Counters> {
+    Json(
+        state
+            .stats
+            .lock()
+            .ok()
+            .and_then(|stats| stats.rooms.get(&id).map(|entry| entry.counters))
+            .unwrap_or_default(),
+    )
+}
+
+pub(super) async fn user_stats(
+    Path(user): Path<String>,
+    State(state): State<Day19State>,
+) -> Json<Counters> {
+    Json(
+        state
+            .stats
+            .lock()
+            .ok()
+            .and_then(|stats| stats.users.get(&user).map(|entry| entry.counters))
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::Stats;
+
+    #[test]
+    fn entries_leave_with_the_last_connection() {
+        let mut stats = Stats::default();
+        stats.connected(1, "santa");
+        stats.connected(1, "santa");
+        stats.connected(2, "santa");
+        stats.sent(1, "santa");
+        stats.delivered(3, "elf", 5);
+        assert_eq!(stats.rooms[&1].counters.messages_sent, 1);
+        assert_eq!(stats.users["santa"].counters.messages_sent, 1);
+        // nobody is connected to room 3 or as the elf
+        assert_eq!(stats.rooms.len(), 2);
+        assert_eq!(stats.users.len(), 1);
+
+        stats.disconnected(1, "santa");
+        assert!(stats.rooms.contains_key(&1));
+        stats.disconnected(1, "santa");
+        assert!(!stats.rooms.contains_key(&1));
+        assert!(stats.users.contains_key("santa"));
+        stats.disconnected(2, "santa");
+        assert!(stats.rooms.is_empty());
+        assert!(stats.users.is_empty());
+    }
+}
@@ -1,7 +1,15 @@
-use std::io::Cursor;
+use std::{
+    collections::{BinaryHeap, HashSet},
+    io::{self, Cursor},
+    path::Path,
+};
 
-use axum::{body::Bytes, response::IntoResponse, routing::post, Json, Router};
-use tar::Archive;
+use axum::{body::Bytes, routing::post, Json, Router};
+use tar::{Archive, EntryType};
+
+use crate::error::AppError;
+
+mod git;
 
 pub(super) fn route() -> Router {
     Router::new()
@@ -10,36 +18,98 @@ pub(super) fn route() -> Router {
     .route("/cookie", post(cookie))
 }
 
-async fn archive_files(body: Bytes) -> Json<usize> {
+async fn archive_files(body: Bytes) -> Result<Json<usize>, AppError> {
     let cursor = Cursor::new(body);
     let mut archive = Archive::new(cursor);
-    match archive.entries() {
-        Ok(entries) => Json(entries.count()),
-        Err(_) => Json(0)
-    }
+    let entries = archive.entries().map_err(AppError::bad_request)?;
+    Ok(Json(entries.count()))
 }
 
-async fn archive_files_size(body: Bytes) -> Json<usize> {
+async fn archive_files_size(body: Bytes) -> Result<Json<usize>, AppError> {
     let cursor = Cursor::new(body);
     let mut archive = Archive::new(cursor);
 
-    match archive.entries() {
-        Ok(entries) => {
-            let mut total_size = 0;
-            for entry in entries {
-                if let Ok(file) = entry {
-                    total_size += file.size() as usize;
-                };
-            }
+    let mut total_size = 0;
+    for entry in archive.entries().map_err(AppError::bad_request)? {
+        total_size += entry.map_err(AppError::bad_request)?.size() as usize;
+    }
+
+    Ok(Json(total_size))
+}
+
+async fn cookie(body: Bytes) -> Result<String, AppError> {
+    tokio::task::spawn_blocking(move || {
+        let dir = tempfile::tempdir().map_err(|e| AppError::Internal(e.to_string()))?;
+        unpack(body, dir.path()).map_err(AppError::bad_request)?;
+        let (author, commit) =
+            find_cookie(&dir.path().join(".git"), "christmas").map_err(AppError::bad_request)?;
+        Ok(format!("{author} {commit}"))
+    })
+    .await
+    .map_err(|e| AppError::Internal(e.to_string()))?
+}
 
-            Json(total_size)
+/// Unpacks the regular files and directories of a tarball into `dir`. Links
+/// are left out, they could point the repository reads at a device file or
+/// anywhere else on the host.
+fn unpack(tarball: Bytes, dir: &Path) -> io::Result<()> {
+    let mut archive = Archive::new(Cursor::new(tarball));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if matches!(
+            entry.header().entry_type(),
+            EntryType::Regular | EntryType::Directory
+        ) {
+            entry.unpack_in(dir)?;
         }
-        Err(_) => Json(0),
     }
+    Ok(())
 }
 
-async fn cookie(body: Bytes) -> impl IntoResponse {
-    todo!()
+/// Walks the history of `branch` newest commit first and returns the author and
+/// id of the first commit whose tree holds a `santa.txt` containing "COOKIE".
+fn find_cookie(git_dir: &Path, branch: &str) -> io::Result<(String, git::Oid)> {
+    let repo = git::Repository::open(git_dir)?;
+    let head = repo.branch(branch)?.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("no branch named {branch}"))
+    })?;
+
+    let mut seen = HashSet::from([head]);
+    let mut queue = BinaryHeap::from([(repo.commit(&head)?.time, head)]);
+    while let Some((_, oid)) = queue.pop() {
+        let commit = repo.commit(&oid)?;
+        if has_cookie(&repo, &commit.tree)? {
+            return Ok((commit.author, oid));
+        }
+        for parent in commit.parents {
+            if seen.insert(parent) {
+                queue.push((repo.commit(&parent)?.time, parent));
+            }
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::NotFound, "no cookie found"))
+}
+
+/// Whether `tree` or any tree below it holds the cookie. A tree is searched
+/// once however often it's listed, so one that lists itself can't loop.
+fn has_cookie(repo: &git::Repository, tree: &git::Oid) -> io::Result<bool> {
+    let mut seen = HashSet::from([*tree]);
+    let mut trees = vec![*tree];
+    while let Some(tree) = trees.pop() {
+        for entry in repo.tree(&tree)? {
+            if entry.is_dir {
+                if seen.insert(entry.oid) {
+                    trees.push(entry.oid);
+                }
+            } else if entry.name == "santa.txt"
+                && String::from_utf8_lossy(&repo.blob(&entry.oid)?).contains("COOKIE")
+            {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
 }
 
 
@@ -64,7 +134,19 @@ mod test {
             .assert_text("1196282");
     }
 
-    // #[tokio::test]
+    #[tokio::test]
+    async fn malformed() {
+        let res = routes_test()
+            .await
+            .post("/20/archive_files_size")
+            .bytes(Bytes::from_static(&[1; 1024]))
+            .expect_failure()
+            .await;
+        res.assert_status_bad_request();
+        res.assert_json_contains(&serde_json::json!({"status": 400}));
+    }
+
+    #[tokio::test]
     async fn task2() {
         let c = Bytes::from_static(include_bytes!("../../assets/cookiejar.tar"));
         routes_test()
@@ -74,4 +156,58 @@ mod test {
             .await
             .assert_text("Grinch 71dfab551a1958b35b7436c54b7455dcec99a12c");
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn task2_packed() {
+        // same repository after `git gc`: packfile with deltas and packed-refs
+        let c = Bytes::from_static(include_bytes!("../../assets/cookiejar_packed.tar"));
+        routes_test()
+            .await
+            .post("/20/cookie")
+            .bytes(c)
+            .await
+            .assert_text("Grinch 71dfab551a1958b35b7436c54b7455dcec99a12c");
+    }
+
+    #[tokio::test]
+    async fn symlinked_ref() {
+        let mut tarball = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        tarball
+            .append_link(&mut header, ".git/refs/heads/christmas", "/dev/zero")
+            .unwrap();
+        let res = routes_test()
+            .await
+            .post("/20/cookie")
+            .bytes(tarball.into_inner().unwrap().into())
+            .expect_failure()
+            .await;
+        // the link isn't unpacked, so there's no branch to read
+        res.assert_status_bad_request();
+        res.assert_json_contains(&serde_json::json!({"detail": "no branch named christmas"}));
+    }
+
+    #[test]
+    fn tree_listing_itself() {
+        use std::io::Write;
+
+        // object ids aren't checked against their content, so a tree can
+        // claim to contain itself
+        let dir = tempfile::tempdir().unwrap();
+        let hex = "ab".repeat(20);
+        let mut tree = b"tree 28\x0040000 x\0".to_vec();
+        tree.extend([0xab; 20]);
+        let objects = dir.path().join("objects").join(&hex[..2]);
+        std::fs::create_dir_all(&objects).unwrap();
+        let file = std::fs::File::create(objects.join(&hex[2..])).unwrap();
+        let mut zip = flate2::write::ZlibEncoder::new(file, flate2::Compression::default());
+        zip.write_all(&tree).unwrap();
+        zip.finish().unwrap();
+
+        let repo = super::git::Repository::open(dir.path()).unwrap();
+        let oid = super::git::Oid::from_hex(&hex).unwrap();
+        assert!(!super::has_cookie(&repo, &oid).unwrap());
+    }
+}
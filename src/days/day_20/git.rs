@@ -0,0 +1,581 @@
+//! Just enough of the git object store to walk commits and trees: loose
+//! objects, v2 pack indexes, packfiles with ofs/ref deltas and packed refs.
+
+use std::{
+    fmt, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::ZlibDecoder;
+
+/// Deltas followed to reach a base object. Git itself stops at 50 by default,
+/// and a crafted pack could otherwise make a chain as long as it likes.
+const MAX_DELTA_DEPTH: usize = 50;
+/// Bytes an object may inflate to, a zlib stream can expand a thousandfold.
+const MAX_OBJECT_SIZE: u64 = 64 << 20;
+/// Bytes read from any one file of the repository, packs included.
+const MAX_FILE_SIZE: u64 = 256 << 20;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Opens `path` only if it's a regular file, never following a link to a
+/// device or anywhere else on the host, and reads at most one byte past
+/// [`MAX_FILE_SIZE`] from it.
+fn open(path: &Path) -> io::Result<io::Take<fs::File>> {
+    if !fs::symlink_metadata(path)?.file_type().is_file() {
+        return Err(invalid("repository files have to be regular files"));
+    }
+    Ok(fs::File::open(path)?.take(MAX_FILE_SIZE + 1))
+}
+
+fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    open(path)?.read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_FILE_SIZE {
+        return Err(invalid("repository file too large"));
+    }
+    Ok(data)
+}
+
+fn read_to_string(path: &Path) -> io::Result<String> {
+    String::from_utf8(read(path)?).map_err(|_| invalid("repository file is not utf-8"))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(super) struct Oid([u8; 20]);
+
+impl Oid {
+    pub(super) fn from_hex(hex: &str) -> io::Result<Self> {
+        let hex = hex.trim().as_bytes();
+        if hex.len() != 40 {
+            return Err(invalid("object id must be 40 hex digits"));
+        }
+        let mut oid = [0; 20];
+        for (byte, pair) in oid.iter_mut().zip(hex.chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid("object id is not hex"))?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid("object id is not hex"))?;
+        }
+        Ok(Oid(oid))
+    }
+
+    fn from_slice(bytes: &[u8]) -> io::Result<Self> {
+        bytes
+            .try_into()
+            .map(Oid)
+            .map_err(|_| invalid("truncated object id"))
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Kind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl Kind {
+    fn from_name(name: &[u8]) -> io::Result<Self> {
+        match name {
+            b"commit" => Ok(Kind::Commit),
+            b"tree" => Ok(Kind::Tree),
+            b"blob" => Ok(Kind::Blob),
+            b"tag" => Ok(Kind::Tag),
+            _ => Err(invalid("unknown object type")),
+        }
+    }
+
+    fn from_pack_type(ty: u8) -> io::Result<Self> {
+        match ty {
+            1 => Ok(Kind::Commit),
+            2 => Ok(Kind::Tree),
+            3 => Ok(Kind::Blob),
+            4 => Ok(Kind::Tag),
+            _ => Err(invalid("unknown pack object type")),
+        }
+    }
+}
+
+pub(super) struct Object {
+    pub(super) kind: Kind,
+    pub(super) data: Vec<u8>,
+}
+
+pub(super) struct Commit {
+    pub(super) tree: Oid,
+    pub(super) parents: Vec<Oid>,
+    pub(super) author: String,
+    pub(super) time: i64,
+}
+
+impl Commit {
+    fn parse(data: &[u8]) -> io::Result<Self> {
+        let text = String::from_utf8_lossy(data);
+        let (mut tree, mut parents, mut author, mut time) = (None, Vec::new(), None, 0);
+        // headers end at the first blank line, the message follows
+        for line in text.lines().take_while(|line| !line.is_empty()) {
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            match key {
+                "tree" => tree = Some(Oid::from_hex(value)?),
+                "parent" => parents.push(Oid::from_hex(value)?),
+                "author" => {
+                    let name = value.split(" <").next().unwrap_or(value);
+                    author = Some(name.to_string());
+                }
+                "committer" => {
+                    // "<name> <<email>> <unix time> <tz>"
+                    time = value
+                        .rsplit(' ')
+                        .nth(1)
+                        .and_then(|t| t.parse().ok())
+                        .unwrap_or_default();
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Commit {
+            tree: tree.ok_or_else(|| invalid("commit without tree"))?,
+            parents,
+            author: author.ok_or_else(|| invalid("commit without author"))?,
+            time,
+        })
+    }
+}
+
+pub(super) struct TreeEntry {
+    pub(super) name: String,
+    pub(super) oid: Oid,
+    pub(super) is_dir: bool,
+}
+
+fn parse_tree(mut data: &[u8]) -> io::Result<Vec<TreeEntry>> {
+    let mut entries = Vec::new();
+    // each entry is "<mode> <name>\0<20 byte id>"
+    while !data.is_empty() {
+        let space = data
+            .iter()
+            .position(|&b| b == b' ')
+            .ok_or_else(|| invalid("truncated tree entry"))?;
+        let nul = data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid("truncated tree entry"))?;
+        if nul < space || data.len() < nul + 21 {
+            return Err(invalid("truncated tree entry"));
+        }
+        entries.push(TreeEntry {
+            is_dir: &data[..space] == b"40000",
+            name: String::from_utf8_lossy(&data[space + 1..nul]).into_owned(),
+            oid: Oid::from_slice(&data[nul + 1..nul + 21])?,
+        });
+        data = &data[nul + 21..];
+    }
+    Ok(entries)
+}
+
+struct Pack {
+    index: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl Pack {
+    fn open(idx_path: &Path) -> io::Result<Self> {
+        let index = read(idx_path)?;
+        let data = read(&idx_path.with_extension("pack"))?;
+        if index.len() < 8 + 256 * 4 || index[..8] != [0xff, b't', b'O', b'c', 0, 0, 0, 2] {
+            return Err(invalid("only version 2 pack indexes are supported"));
+        }
+        if data.len() < 12 || &data[..4] != b"PACK" {
+            return Err(invalid("bad pack header"));
+        }
+        Ok(Pack { index, data })
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> io::Result<u32> {
+        bytes
+            .get(at..at + 4)
+            .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+            .ok_or_else(|| invalid("truncated pack index"))
+    }
+
+    fn count(&self) -> io::Result<usize> {
+        Self::u32_at(&self.index, 8 + 255 * 4).map(|n| n as usize)
+    }
+
+    /// Looks `oid` up in the index and returns its offset into the pack.
+    fn find(&self, oid: &Oid) -> io::Result<Option<usize>> {
+        const FANOUT: usize = 8;
+        const NAMES: usize = FANOUT + 256 * 4;
+        let first = oid.0[0] as usize;
+        let lo = match first {
+            0 => 0,
+            _ => Self::u32_at(&self.index, FANOUT + (first - 1) * 4)? as usize,
+        };
+        let hi = Self::u32_at(&self.index, FANOUT + first * 4)? as usize;
+        let count = self.count()?;
+
+        let names = self
+            .index
+            .get(NAMES + lo * 20..NAMES + hi * 20)
+            .ok_or_else(|| invalid("truncated pack index"))?;
+        let Ok(pos) = names
+            .chunks(20)
+            .collect::<Vec<_>>()
+            .binary_search(&oid.0.as_slice())
+        else {
+            return Ok(None);
+        };
+        let pos = lo + pos;
+
+        let offsets = NAMES + count * 24;
+        let offset = Self::u32_at(&self.index, offsets + pos * 4)?;
+        if offset & 0x8000_0000 == 0 {
+            return Ok(Some(offset as usize));
+        }
+        let large = offsets + count * 4 + (offset & 0x7fff_ffff) as usize * 8;
+        self.index
+            .get(large..large + 8)
+            .map(|b| Some(u64::from_be_bytes(b.try_into().unwrap()) as usize))
+            .ok_or_else(|| invalid("truncated pack index"))
+    }
+
+    /// `depth` counts the deltas already followed to get here.
+    fn read_at(&self, repo: &Repository, offset: usize, depth: usize) -> io::Result<Object> {
+        if depth > MAX_DELTA_DEPTH {
+            return Err(invalid("delta chain too deep"));
+        }
+        let byte_at = |at: usize| {
+            self.data
+                .get(at)
+                .copied()
+                .ok_or_else(|| invalid("truncated pack"))
+        };
+
+        let mut at = offset;
+        let mut c = byte_at(at)?;
+        let ty = (c >> 4) & 0x7;
+        // the inflated size is implied by the zlib stream, skip it
+        while c & 0x80 != 0 {
+            at += 1;
+            c = byte_at(at)?;
+        }
+        at += 1;
+
+        match ty {
+            6 => {
+                let mut c = byte_at(at)?;
+                let mut distance = (c & 0x7f) as usize;
+                while c & 0x80 != 0 {
+                    at += 1;
+                    c = byte_at(at)?;
+                    distance = ((distance + 1) << 7) | (c & 0x7f) as usize;
+                }
+                if distance == 0 {
+                    return Err(invalid("delta is its own base"));
+                }
+                let base_offset = offset
+                    .checked_sub(distance)
+                    .ok_or_else(|| invalid("delta base before start of pack"))?;
+                let base = self.read_at(repo, base_offset, depth + 1)?;
+                let delta = self.inflate(at + 1)?;
+                Ok(Object {
+                    kind: base.kind,
+                    data: apply_delta(&base.data, &delta)?,
+                })
+            }
+            7 => {
+                let base_oid = Oid::from_slice(
+                    self.data
+                        .get(at..at + 20)
+                        .ok_or_else(|| invalid("truncated pack"))?,
+                )?;
+                let base = repo.read_at_depth(&base_oid, depth + 1)?;
+                let delta = self.inflate(at + 20)?;
+                Ok(Object {
+                    kind: base.kind,
+                    data: apply_delta(&base.data, &delta)?,
+                })
+            }
+            ty => Ok(Object {
+                kind: Kind::from_pack_type(ty)?,
+                data: self.inflate(at)?,
+            }),
+        }
+    }
+
+    fn inflate(&self, at: usize) -> io::Result<Vec<u8>> {
+        inflate(
+            self.data
+                .get(at..)
+                .ok_or_else(|| invalid("truncated pack"))?,
+        )
+    }
+}
+
+/// Inflates a zlib stream of at most [`MAX_OBJECT_SIZE`] bytes.
+fn inflate(compressed: impl Read) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    ZlibDecoder::new(compressed)
+        .take(MAX_OBJECT_SIZE + 1)
+        .read_to_end(&mut out)?;
+    if out.len() as u64 > MAX_OBJECT_SIZE {
+        return Err(invalid("object too large"));
+    }
+    Ok(out)
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let mut delta = delta.iter().copied();
+    let mut size = || {
+        let (mut value, mut shift) = (0usize, 0);
+        for c in delta.by_ref() {
+            value |= ((c & 0x7f) as usize) << shift;
+            shift += 7;
+            if c & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("truncated delta header"))
+    };
+    let base_size = size()?;
+    let result_size = size()?;
+    if base_size != base.len() {
+        return Err(invalid("delta base size mismatch"));
+    }
+    if result_size as u64 > MAX_OBJECT_SIZE {
+        return Err(invalid("object too large"));
+    }
+
+    let mut out = Vec::with_capacity(result_size);
+    while let Some(op) = delta.next() {
+        if op & 0x80 != 0 {
+            // copy from base: bits 0-3 select offset bytes, bits 4-6 size bytes
+            let mut read = |bits: std::ops::Range<u8>| {
+                let mut value = 0usize;
+                for (i, bit) in bits.enumerate() {
+                    if op & (1 << bit) != 0 {
+                        let byte = delta.next().ok_or_else(|| invalid("truncated delta"))?;
+                        value |= (byte as usize) << (i * 8);
+                    }
+                }
+                Ok::<_, io::Error>(value)
+            };
+            let offset = read(0..4)?;
+            let len = match read(4..7)? {
+                0 => 0x10000,
+                len => len,
+            };
+            out.extend_from_slice(
+                base.get(offset..offset + len)
+                    .ok_or_else(|| invalid("delta copy out of range"))?,
+            );
+        } else if op != 0 {
+            let start = out.len();
+            out.extend(delta.by_ref().take(op as usize));
+            if out.len() - start != op as usize {
+                return Err(invalid("truncated delta"));
+            }
+        } else {
+            return Err(invalid("reserved delta opcode"));
+        }
+    }
+
+    if out.len() != result_size {
+        return Err(invalid("delta result size mismatch"));
+    }
+    Ok(out)
+}
+
+pub(super) struct Repository {
+    git_dir: PathBuf,
+    packs: Vec<Pack>,
+}
+
+impl Repository {
+    pub(super) fn open(git_dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let git_dir = git_dir.into();
+        let mut packs = Vec::new();
+        if let Ok(dir) = fs::read_dir(git_dir.join("objects/pack")) {
+            for entry in dir {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "idx") {
+                    packs.push(Pack::open(&path)?);
+                }
+            }
+        }
+        Ok(Repository { git_dir, packs })
+    }
+
+    /// Resolves `refs/heads/<branch>`, loose ref first, then `packed-refs`.
+    pub(super) fn branch(&self, branch: &str) -> io::Result<Option<Oid>> {
+        let name = format!("refs/heads/{branch}");
+        match read_to_string(&self.git_dir.join(&name)) {
+            Ok(oid) => return Oid::from_hex(&oid).map(Some),
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            Err(_) => {}
+        }
+
+        let packed = match read_to_string(&self.git_dir.join("packed-refs")) {
+            Ok(packed) => packed,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        packed
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+            .filter_map(|line| line.split_once(' '))
+            .find(|(_, r)| *r == name)
+            .map(|(oid, _)| Oid::from_hex(oid))
+            .transpose()
+    }
+
+    pub(super) fn read(&self, oid: &Oid) -> io::Result<Object> {
+        self.read_at_depth(oid, 0)
+    }
+
+    fn read_at_depth(&self, oid: &Oid, depth: usize) -> io::Result<Object> {
+        let hex = oid.to_string();
+        let loose = self.git_dir.join("objects").join(&hex[..2]).join(&hex[2..]);
+        match open(&loose) {
+            Ok(file) => return Self::read_loose(file),
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            Err(_) => {}
+        }
+
+        for pack in &self.packs {
+            if let Some(offset) = pack.find(oid)? {
+                return pack.read_at(self, offset, depth);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("object {hex} not found"),
+        ))
+    }
+
+    fn read_loose(file: impl Read) -> io::Result<Object> {
+        let raw = inflate(file)?;
+        // "<type> <size>\0<data>"
+        let nul = raw
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid("bad loose object header"))?;
+        let kind = raw[..nul]
+            .split(|&b| b == b' ')
+            .next()
+            .ok_or_else(|| invalid("bad loose object header"))?;
+        Ok(Object {
+            kind: Kind::from_name(kind)?,
+            data: raw[nul + 1..].to_vec(),
+        })
+    }
+
+    fn read_kind(&self, oid: &Oid, kind: Kind) -> io::Result<Vec<u8>> {
+        let object = self.read(oid)?;
+        if object.kind != kind {
+            return Err(invalid(format!(
+                "{oid} is a {:?}, expected {kind:?}",
+                object.kind
+            )));
+        }
+        Ok(object.data)
+    }
+
+    pub(super) fn commit(&self, oid: &Oid) -> io::Result<Commit> {
+        Commit::parse(&self.read_kind(oid, Kind::Commit)?)
+    }
+
+    pub(super) fn tree(&self, oid: &Oid) -> io::Result<Vec<TreeEntry>> {
+        parse_tree(&self.read_kind(oid, Kind::Tree)?)
+    }
+
+    pub(super) fn blob(&self, oid: &Oid) -> io::Result<Vec<u8>> {
+        self.read_kind(oid, Kind::Blob)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply_delta, Oid, Pack, Repository};
+
+    /// A pack of the given objects, the index listing only `oid` at offset 12.
+    fn packed(oid: Oid, objects: &[u8]) -> Repository {
+        let mut index = vec![0xff, b't', b'O', b'c', 0, 0, 0, 2];
+        for first in 0..=255u8 {
+            let count = u32::from(first >= oid.0[0]);
+            index.extend(count.to_be_bytes());
+        }
+        index.extend(oid.0);
+        index.extend([0; 4]);
+        index.extend(12u32.to_be_bytes());
+        let mut data = b"PACK\0\0\0\x02\0\0\0\x01".to_vec();
+        data.extend(objects);
+        Repository {
+            git_dir: "/nonexistent".into(),
+            packs: vec![Pack { index, data }],
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_are_not_followed() {
+        let dir = tempfile::tempdir().unwrap();
+        let heads = dir.path().join("refs/heads");
+        std::fs::create_dir_all(&heads).unwrap();
+        std::os::unix::fs::symlink("/dev/zero", heads.join("christmas")).unwrap();
+        let repo = Repository::open(dir.path()).unwrap();
+        let err = repo.branch("christmas").err().unwrap();
+        assert_eq!(err.to_string(), "repository files have to be regular files");
+    }
+
+    #[test]
+    fn delta_cycles() {
+        let oid = Oid([0xab; 20]);
+        // an ofs-delta 0 bytes back is itself
+        let repo = packed(oid, &[0x60, 0x00]);
+        assert!(repo.read(&oid).is_err());
+        // a ref-delta on its own id
+        let mut objects = vec![0x70];
+        objects.extend(oid.0);
+        let repo = packed(oid, &objects);
+        let err = repo.read(&oid).err().unwrap();
+        assert_eq!(err.to_string(), "delta chain too deep");
+    }
+
+    #[test]
+    fn inflate_cap() {
+        use std::io::Write;
+
+        let mut zip = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+        for _ in 0..=super::MAX_OBJECT_SIZE >> 20 {
+            zip.write_all(&[0; 1 << 20]).unwrap();
+        }
+        let zipped = zip.finish().unwrap();
+        assert!(zipped.len() < 1 << 20);
+        assert!(super::inflate(zipped.as_slice()).is_err());
+    }
+
+    #[test]
+    fn delta() {
+        // copy "hello" from base, insert ", git", copy " world"
+        let delta = [11, 16, 0x90, 5, 5, b',', b' ', b'g', b'i', b't', 0x91, 5, 6];
+        assert_eq!(
+            apply_delta(b"hello world", &delta).unwrap(),
+            b"hello, git world"
+        );
+        assert!(apply_delta(b"hello world", &[11, 16, 0]).is_err());
+        assert!(apply_delta(b"hello", &delta).is_err());
+    }
+}
@@ -1,40 +1,36 @@
-use std::{num::NonZeroU32, sync::Arc};
+use std::sync::Arc;
 
 use axum::{
     extract::{Path, State},
     routing::get,
     Router,
 };
-use governor::{
-    clock,
-    middleware::NoOpMiddleware,
-    state::{InMemoryState, NotKeyed},
-};
 use s2::{cellid::CellID, latlng::LatLng};
 
-struct Day21State {
-    client: reqwest::Client,
-    rate_limiter:
-        governor::RateLimiter<NotKeyed, InMemoryState, clock::DefaultClock, NoOpMiddleware>,
-}
+use crate::error::AppError;
+
+mod geocode;
+
+const COUNTRIES: &str = include_str!("../../assets/countries.geojson");
 
 pub(super) fn route() -> Router {
+    let geocoder = geocode::Geocoder::from_geojson(COUNTRIES).expect("bundled countries asset");
     Router::new()
         .route("/coords/:binary", get(coords))
         .route("/country/:binary", get(country))
-        .with_state(Arc::new(Day21State {
-            client: reqwest::Client::new(),
-            rate_limiter: governor::RateLimiter::direct(governor::Quota::per_second(
-                NonZeroU32::new(1).unwrap(),
-            )),
-        }))
+        .with_state(Arc::new(geocoder))
+}
+
+fn parse_cell(binary: &str) -> Result<LatLng, AppError> {
+    let cell = u64::from_str_radix(binary, 2).map(CellID)?;
+    if !cell.is_valid() {
+        return Err(AppError::bad_request("not a valid S2 cell id"));
+    }
+    Ok(LatLng::from(cell))
 }
 
-async fn coords(Path(binary): Path<String>) -> String {
-    let lat_long = u64::from_str_radix(&binary, 2)
-        .map(CellID)
-        .map(LatLng::from)
-        .unwrap();
+async fn coords(Path(binary): Path<String>) -> Result<String, AppError> {
+    let lat_long = parse_cell(&binary)?;
 
     let lat = lat_long.lat.deg();
     let lng = lat_long.lng.deg();
@@ -42,48 +38,25 @@ async fn coords(Path(binary): Path<String>) -> String {
     let latitude_indicator = if lat >= 0.0 { 'N' } else { 'S' };
     let longitude_indicator = if lng >= 0.0 { 'E' } else { 'W' };
 
-    format!(
+    Ok(format!(
         "{}{} {}{}",
         degrees_to_dms(lat),
         latitude_indicator,
         degrees_to_dms(lng),
         longitude_indicator
-    )
+    ))
 }
 
-#[derive(serde::Deserialize)]
-struct Record {
-    address: Address,
-}
+async fn country(
+    State(geocoder): State<Arc<geocode::Geocoder>>,
+    Path(binary): Path<String>,
+) -> Result<String, AppError> {
+    let lat_long = parse_cell(&binary)?;
 
-#[derive(serde::Deserialize)]
-struct Address {
-    country: String,
-}
-
-async fn country(State(s): State<Arc<Day21State>>, Path(binary): Path<String>) -> String {
-    let lat_long = u64::from_str_radix(&binary, 2)
-        .map(CellID)
-        .map(LatLng::from)
-        .unwrap();
-
-    let lat = lat_long.lat.deg();
-    let lng = lat_long.lng.deg();
-
-    tokio::time::interval(tokio::time::Duration::from_secs(2)).tick().await;
-    s.rate_limiter.until_ready().await;
-    s.client
-        .get(format!(
-            "https://geocode.maps.co/reverse?lat={lat}&lon={lng}&api_key=???"
-        ))
-        .send()
-        .await
-        .unwrap()
-        .json::<Record>()
-        .await
-        .unwrap()
-        .address
-        .country
+    geocoder
+        .country(&lat_long)
+        .map(ToString::to_string)
+        .ok_or_else(|| AppError::NotFound(format!("no country at {lat_long}")))
 }
 
 fn degrees_to_dms(degrees: f64) -> String {
@@ -113,7 +86,17 @@ mod test {
             .assert_text("18°54'55.944''S 47°31'17.976''E");
     }
 
-    // #[tokio::test]
+    #[tokio::test]
+    async fn malformed() {
+        let server = routes_test().await;
+        for path in ["/21/coords/0102", "/21/coords/0", "/21/country/banana"] {
+            let res = server.get(path).expect_failure().await;
+            res.assert_status_bad_request();
+            res.assert_json_contains(&serde_json::json!({"status": 400}));
+        }
+    }
+
+    #[tokio::test]
     async fn task2() {
         let server = routes_test().await;
         server
@@ -124,5 +107,43 @@ mod test {
             .get("/21/country/0011001000100010100010110001110100000111000010111000100000010101")
             .await
             .assert_text("Brunei");
+        // middle of the Indian Ocean
+        server
+            .get("/21/country/0010001010100000000000000000000000000000000000000000000000000001")
+            .expect_failure()
+            .await
+            .assert_status_not_found();
+    }
+
+    #[tokio::test]
+    async fn outside_the_known_countries() {
+        let server = routes_test().await;
+        // Paris and Ajaccio, on Corsica
+        for (lat, lng) in [(48.86, 2.35), (41.93, 8.74)] {
+            let cell = s2::cellid::CellID::from(s2::latlng::LatLng::from_degrees(lat, lng));
+            server
+                .get(&format!("/21/country/{:064b}", cell.0))
+                .await
+                .assert_text("France");
+        }
+        // next to Brunei, Madagascar and France but outside them, within their
+        // boxes' cells, and out in the oceans
+        for (lat, lng) in [
+            (5.98, 116.07),
+            (4.7, 114.2),
+            (-15.0, 40.5),
+            (-25.5, 44.0),
+            (45.5, -4.0),
+            (45.0, -30.0),
+            (-16.0, 179.5),
+            (0.0, 0.0),
+        ] {
+            let cell = s2::cellid::CellID::from(s2::latlng::LatLng::from_degrees(lat, lng));
+            server
+                .get(&format!("/21/country/{:064b}", cell.0))
+                .expect_failure()
+                .await
+                .assert_status_not_found();
+        }
     }
 }
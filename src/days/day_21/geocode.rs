@@ -0,0 +1,277 @@
+//! Offline reverse geocoder over admin-0 country polygons in GeoJSON.
+//!
+//! Each polygon's bounding box is covered with S2 cells; a lookup walks the
+//! ancestors of the point's leaf cell to collect candidate polygons and then
+//! runs an exact point-in-polygon test on them. Polygons crossing the
+//! antimeridian are split there first, both the boxes and the ray casting
+//! assume longitudes don't wrap.
+
+use std::collections::HashMap;
+
+use s2::{cellid::CellID, latlng::LatLng, rect::Rect, region::RegionCoverer};
+use serde_json::Value;
+
+/// Deepest level used for coverings, cells are ~40km across at level 8.
+const MAX_LEVEL: u8 = 8;
+
+/// A ring of (lng, lat) vertices in degrees.
+type Ring = Vec<(f64, f64)>;
+
+struct Polygon {
+    country: usize,
+    exterior: Ring,
+    holes: Vec<Ring>,
+}
+
+impl Polygon {
+    fn contains(&self, lng: f64, lat: f64) -> bool {
+        ring_contains(&self.exterior, lng, lat)
+            && !self.holes.iter().any(|hole| ring_contains(hole, lng, lat))
+    }
+
+    fn bound(&self) -> Rect {
+        let (mut lat_lo, mut lng_lo, mut lat_hi, mut lng_hi) = (90.0, 180.0, -90.0, -180.0);
+        for &(lng, lat) in &self.exterior {
+            lat_lo = f64::min(lat_lo, lat);
+            lat_hi = f64::max(lat_hi, lat);
+            lng_lo = f64::min(lng_lo, lng);
+            lng_hi = f64::max(lng_hi, lng);
+        }
+        Rect::from_degrees(lat_lo, lng_lo, lat_hi, lng_hi)
+    }
+}
+
+/// Splits a polygon crossing the antimeridian into the parts on either side,
+/// whether its rings jump from 180 to -180 or run past 180.
+fn split_antimeridian(exterior: Ring, holes: Vec<Ring>) -> Vec<(Ring, Vec<Ring>)> {
+    let exterior = unwrap(exterior);
+    let holes: Vec<Ring> = holes.into_iter().map(unwrap).collect();
+    let (lo, hi) = exterior
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(lo, hi), &(lng, _)| {
+            (lo.min(lng), hi.max(lng))
+        });
+    if lo >= -180.0 && hi <= 180.0 {
+        return vec![(exterior, holes)];
+    }
+
+    // one part where the ring is, the other shifted a turn back into range
+    let shift = if hi > 180.0 { -360.0 } else { 360.0 };
+    [0.0, shift]
+        .into_iter()
+        .filter_map(|shift| {
+            let part = |ring: &Ring| {
+                let shifted = ring.iter().map(|&(lng, lat)| (lng + shift, lat));
+                clip(clip(shifted.collect(), -180.0, 1.0), 180.0, -1.0)
+            };
+            let exterior = part(&exterior);
+            let holes = holes.iter().map(part).filter(|h| h.len() >= 3).collect();
+            (exterior.len() >= 3).then_some((exterior, holes))
+        })
+        .collect()
+}
+
+/// Makes longitudes continuous: each vertex is moved by whole turns to within
+/// 180 degrees of the one before, so a ring may run past ±180.
+fn unwrap(ring: Ring) -> Ring {
+    let mut out: Ring = Vec::with_capacity(ring.len());
+    for (mut lng, lat) in ring {
+        if let Some(&(prev, _)) = out.last() {
+            lng += ((prev - lng) / 360.0).round() * 360.0;
+        }
+        out.push((lng, lat));
+    }
+    out
+}
+
+/// Sutherland-Hodgman: the part of `ring` on the side of the meridian `at`
+/// where `side * (lng - at) >= 0`.
+fn clip(ring: Ring, at: f64, side: f64) -> Ring {
+    let inside = |&(lng, _): &(f64, f64)| side * (lng - at) >= 0.0;
+    let mut out = Vec::with_capacity(ring.len());
+    for (i, &a) in ring.iter().enumerate() {
+        let b = ring[(i + 1) % ring.len()];
+        if inside(&a) {
+            out.push(a);
+        }
+        if inside(&a) != inside(&b) {
+            let t = (at - a.0) / (b.0 - a.0);
+            out.push((at, a.1 + t * (b.1 - a.1)));
+        }
+    }
+    out
+}
+
+/// Even-odd ray casting, good enough for simplified country outlines.
+fn ring_contains(ring: &[(f64, f64)], lng: f64, lat: f64) -> bool {
+    let mut inside = false;
+    for (i, &(x1, y1)) in ring.iter().enumerate() {
+        let (x2, y2) = ring[(i + 1) % ring.len()];
+        if (y1 > lat) != (y2 > lat) && lng < (x2 - x1) * (lat - y1) / (y2 - y1) + x1 {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+pub(super) struct Geocoder {
+    countries: Vec<String>,
+    polygons: Vec<Polygon>,
+    index: HashMap<CellID, Vec<usize>>,
+}
+
+impl Geocoder {
+    pub(super) fn from_geojson(geojson: &str) -> Result<Self, String> {
+        let collection: Value = serde_json::from_str(geojson).map_err(|e| e.to_string())?;
+        let features = collection["features"]
+            .as_array()
+            .ok_or("expected a FeatureCollection")?;
+
+        let mut countries = Vec::new();
+        let mut polygons = Vec::new();
+        for feature in features {
+            let properties = &feature["properties"];
+            let name = ["ADMIN", "NAME", "name"]
+                .iter()
+                .find_map(|key| properties[key].as_str())
+                .ok_or("feature without a country name")?;
+            let country = countries.len();
+            countries.push(name.to_string());
+
+            let geometry = &feature["geometry"];
+            let parts = match geometry["type"].as_str() {
+                Some("Polygon") => vec![&geometry["coordinates"]],
+                Some("MultiPolygon") => geometry["coordinates"]
+                    .as_array()
+                    .ok_or("malformed MultiPolygon")?
+                    .iter()
+                    .collect(),
+                _ => return Err(format!("unsupported geometry for {name}")),
+            };
+            for part in parts {
+                let mut rings = part
+                    .as_array()
+                    .ok_or("malformed Polygon")?
+                    .iter()
+                    .map(parse_ring)
+                    .collect::<Result<Vec<_>, _>>()?
+                    .into_iter();
+                let exterior = rings.next().ok_or("polygon without exterior ring")?;
+                for (exterior, holes) in split_antimeridian(exterior, rings.collect()) {
+                    polygons.push(Polygon {
+                        country,
+                        exterior,
+                        holes,
+                    });
+                }
+            }
+        }
+
+        let coverer = RegionCoverer {
+            min_level: 0,
+            max_level: MAX_LEVEL,
+            level_mod: 1,
+            max_cells: 8,
+        };
+        let mut index = HashMap::<CellID, Vec<usize>>::new();
+        for (i, polygon) in polygons.iter().enumerate() {
+            for cell in coverer.covering(&polygon.bound()).0 {
+                index.entry(cell).or_default().push(i);
+            }
+        }
+
+        Ok(Geocoder {
+            countries,
+            polygons,
+            index,
+        })
+    }
+
+    pub(super) fn country(&self, point: &LatLng) -> Option<&str> {
+        let (lat, lng) = (point.lat.deg(), point.lng.deg());
+        let leaf = CellID::from(*point);
+        (0..=u64::from(MAX_LEVEL))
+            .filter_map(|level| self.index.get(&leaf.parent(level)))
+            .flatten()
+            .map(|&i| &self.polygons[i])
+            .find(|polygon| polygon.contains(lng, lat))
+            .map(|polygon| self.countries[polygon.country].as_str())
+    }
+}
+
+fn parse_ring(ring: &Value) -> Result<Ring, String> {
+    ring.as_array()
+        .ok_or("malformed ring")?
+        .iter()
+        .map(|position| match position.as_array().map(Vec::as_slice) {
+            Some([lng, lat, ..]) => lng.as_f64().zip(lat.as_f64()).ok_or("malformed position"),
+            _ => Err("malformed position"),
+        })
+        .collect::<Result<_, _>>()
+        .map_err(String::from)
+}
+
+#[cfg(test)]
+mod test {
+    use s2::latlng::LatLng;
+
+    use super::Geocoder;
+
+    #[test]
+    fn holes_and_bounds() {
+        let geocoder = Geocoder::from_geojson(
+            r#"{"type":"FeatureCollection","features":[
+              {"type":"Feature","properties":{"NAME":"Ring"},"geometry":{"type":"Polygon","coordinates":[
+                [[0,0],[10,0],[10,10],[0,10],[0,0]],
+                [[4,4],[6,4],[6,6],[4,6],[4,4]]
+              ]}},
+              {"type":"Feature","properties":{"NAME":"Core"},"geometry":{"type":"Polygon","coordinates":[
+                [[4.5,4.5],[5.5,4.5],[5.5,5.5],[4.5,5.5],[4.5,4.5]]
+              ]}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            geocoder.country(&LatLng::from_degrees(2.0, 2.0)),
+            Some("Ring")
+        );
+        assert_eq!(
+            geocoder.country(&LatLng::from_degrees(5.0, 5.0)),
+            Some("Core")
+        );
+        assert_eq!(geocoder.country(&LatLng::from_degrees(4.2, 5.0)), None);
+        assert_eq!(geocoder.country(&LatLng::from_degrees(-1.0, 2.0)), None);
+    }
+
+    #[test]
+    fn antimeridian() {
+        // one outline jumps from 180 to -180, the other runs past 180
+        let geocoder = Geocoder::from_geojson(
+            r#"{"type":"FeatureCollection","features":[
+              {"type":"Feature","properties":{"ADMIN":"Fiji"},"geometry":{"type":"Polygon","coordinates":[
+                [[178,-17],[-179,-17],[-179,-15],[178,-15],[178,-17]]
+              ]}},
+              {"type":"Feature","properties":{"ADMIN":"Chukotka"},"geometry":{"type":"Polygon","coordinates":[
+                [[175,64],[190,64],[190,68],[175,68],[175,64]]
+              ]}}
+            ]}"#,
+        )
+        .unwrap();
+        for (lat, lng, country) in [
+            (-16.0, 179.5, Some("Fiji")),
+            (-16.0, -179.5, Some("Fiji")),
+            (-16.0, 0.0, None),
+            (-16.0, -178.5, None),
+            (66.0, 176.0, Some("Chukotka")),
+            (66.0, -175.0, Some("Chukotka")),
+            (66.0, 0.0, None),
+            (66.0, -169.0, None),
+        ] {
+            assert_eq!(
+                geocoder.country(&LatLng::from_degrees(lat, lng)),
+                country,
+                "{lat}, {lng}"
+            );
+        }
+    }
+}
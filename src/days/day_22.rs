@@ -1,6 +1,11 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+};
 
-use axum::{body, response::IntoResponse, routing::post, Router};
+use axum::{routing::post, Router};
+
+use crate::error::AppError;
 
 pub(super) fn route() -> Router {
     Router::new()
@@ -8,41 +13,108 @@ pub(super) fn route() -> Router {
     .route("/rocket", post(rocket))
 }
 
-async fn integers(body: String) -> impl IntoResponse {
+/// Gifts answered at most, each is 4 bytes.
+const MAX_GIFTS: u64 = 1 << 20;
+
+async fn integers(body: String) -> Result<String, AppError> {
 
     let numbers = body.lines().filter_map(|line| line.trim().parse::<u64>().ok()).collect::<Vec<u64>>();
     let mut result = 0;
     for num in numbers {
         result ^= num;
     }
+    if result > MAX_GIFTS {
+        return Err(AppError::BadRequest(format!(
+            "{result} gifts is more than the {MAX_GIFTS} that can be wrapped"
+        )));
+    }
+
+    Ok("🎁".repeat(result as usize))
+}
+
+fn next_line<'a>(lines: &mut impl Iterator<Item = &'a str>) -> Result<&'a str, AppError> {
+    lines
+        .next()
+        .ok_or_else(|| AppError::bad_request("unexpected end of input"))
+}
 
-    "🎁".repeat(result as usize)
+fn parse_row<T, const N: usize>(line: &str) -> Result<[T; N], AppError>
+where
+    T: FromStr,
+    AppError: From<T::Err>,
+{
+    line.split_ascii_whitespace()
+        .map(|x| x.parse().map_err(AppError::from))
+        .collect::<Result<Vec<T>, AppError>>()?
+        .try_into()
+        .map_err(|_| AppError::BadRequest(format!("expected {N} values in line {line:?}")))
 }
 
-async fn rocket(body: String) -> impl IntoResponse {
-    let mut lines = body.lines();
-    let number_of_stars = lines.next().map(|x| x.parse().unwrap()).unwrap();
-    let stars: Vec<Vec<i32>> = lines
-        .by_ref()
-        .take(number_of_stars)
-        .map(|x| {
-            x.split_ascii_whitespace()
-                .map(|x| x.parse().unwrap())
-                .collect()
-        })
-        .collect();
-
-    let number_of_portals = lines.next().unwrap().parse().unwrap();
+async fn rocket(body: String) -> Result<String, AppError> {
+    let mut lines = body.lines().map(str::trim);
+    let number_of_stars: usize = next_line(&mut lines)?.parse()?;
+    if number_of_stars == 0 {
+        return Err(AppError::bad_request("at least one star is required"));
+    }
+    let stars = (0..number_of_stars)
+        .map(|_| parse_row::<i32, 3>(next_line(&mut lines)?))
+        .collect::<Result<Vec<_>, AppError>>()?;
+
+    let number_of_portals: usize = next_line(&mut lines)?.parse()?;
     let mut portal_paths = HashMap::new();
-    for p in lines.take(number_of_portals).map(|x| {
-        x.split_ascii_whitespace()
-            .map(|x| x.parse().unwrap())
-            .collect::<Vec<usize>>()
-    }) {
-        portal_paths.entry(p[0]).or_insert(Vec::new()).push(p);
+    for _ in 0..number_of_portals {
+        let [from, to] = parse_row::<usize, 2>(next_line(&mut lines)?)?;
+        if from >= number_of_stars || to >= number_of_stars {
+            return Err(AppError::BadRequest(format!(
+                "portal {from} {to} refers to a star out of range"
+            )));
+        }
+        // portals are two-way
+        portal_paths.entry(from).or_insert(Vec::new()).push(to);
+        portal_paths.entry(to).or_insert(Vec::new()).push(from);
     }
 
-    todo!()
+    let target = number_of_stars - 1;
+    let path = shortest_path(&portal_paths, 0, target)
+        .ok_or_else(|| AppError::BadRequest(format!("star {target} is unreachable")))?;
+
+    let distance = path.windows(2).fold(0.0, |acc, w| {
+        let (a, b) = (stars[w[0]], stars[w[1]]);
+        acc + (0..3)
+            .map(|i| (f64::from(a[i]) - f64::from(b[i])).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    });
+
+    Ok(format!("{} {:.3}", path.len() - 1, distance))
+}
+
+/// Breadth-first search over the portal graph, returns the stars visited
+/// from `start` to `target` inclusive.
+fn shortest_path(
+    portal_paths: &HashMap<usize, Vec<usize>>,
+    start: usize,
+    target: usize,
+) -> Option<Vec<usize>> {
+    let mut previous = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(star) = queue.pop_front() {
+        if star == target {
+            let mut path = vec![target];
+            while let Some(&prev) = previous.get(path.last()?) {
+                path.push(prev);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for &next in portal_paths.get(&star).into_iter().flatten() {
+            if next != start && !previous.contains_key(&next) {
+                previous.insert(next, star);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
 }
 
 
@@ -61,4 +133,67 @@ mod test {
         22
         77").await.assert_text("🎁🎁🎁🎁🎁🎁🎁🎁🎁🎁🎁🎁🎁🎁🎁🎁🎁🎁🎁🎁🎁🎁");
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn task2() {
+        routes_test()
+            .await
+            .post("/22/rocket")
+            .text(
+                "5
+0 1 0
+-2 2 3
+3 -3 -5
+1 1 5
+4 3 5
+4
+0 1
+2 4
+3 4
+1 2
+",
+            )
+            .await
+            .assert_text("3 26.123");
+    }
+
+    #[tokio::test]
+    async fn single_star() {
+        routes_test()
+            .await
+            .post("/22/rocket")
+            .text("1\n1 2 3\n0\n")
+            .await
+            .assert_text("0 0.000");
+    }
+
+    #[tokio::test]
+    async fn malformed() {
+        let server = routes_test().await;
+        for body in [
+            "",
+            "0\n0\n",
+            "2\n0 0 0\n",
+            "1\n0 0\n0",
+            "1\n0 0 0\n1\n0 x",
+            "2\n0 0 0\n1 1 1\n1\n0 2",
+            "3\n0 0 0\n1 1 1\n2 2 2\n1\n0 1",
+        ] {
+            let res = server.post("/22/rocket").text(body).expect_failure().await;
+            res.assert_status_bad_request();
+            res.assert_json_contains(&serde_json::json!({"status": 400}));
+        }
+    }
+
+    #[tokio::test]
+    async fn too_many_gifts() {
+        let res = routes_test()
+            .await
+            .post("/22/integers")
+            .text("18446744073709551615\n")
+            .expect_failure()
+            .await;
+        res.assert_status_bad_request();
+        res.assert_json_contains(&serde_json::json!({"status": 400}));
+    }
+}
@@ -1,5 +1,7 @@
+use std::str::FromStr;
+
 use axum::Router;
-use sqlx::SqlitePool;
+use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
 
 mod day_01;
 mod day_02;
@@ -21,13 +23,14 @@ mod day_19;
 mod day_20;
 mod day_21;
 mod day_22;
+mod store;
 
 pub fn routes(pool: SqlitePool) -> Router {
     Router::new()
         .nest("/1", day_01::route())
         .nest("/2", day_02::route())
         .nest("/3", day_03::route())
-        .nest("/4", day_04::route())
+        .nest("/4", day_04::route(pool.clone()))
         .nest("/5", day_05::route())
         .nest("/6", day_06::route())
         .nest("/7", day_07::route())
@@ -40,18 +43,27 @@ pub fn routes(pool: SqlitePool) -> Router {
         .nest("/14", day_14::route())
         .nest("/15", day_15::route())
         .nest("/18", day_18::route(pool.clone()))
-        .nest("/19", day_19::route())
+        .nest("/19", day_19::route(pool.clone()))
         .nest("/20", day_20::route())
         .nest("/21", day_21::route())
         .nest("/22", day_22::route())
+}
 
+/// Opens the database at `url` and brings its schema up to date. Foreign keys
+/// are asked for explicitly, the orders and regions rely on them.
+pub async fn pool(url: &str) -> sqlx::Result<SqlitePool> {
+    let options = SqliteConnectOptions::from_str(url)?.foreign_keys(true);
+    let pool = SqlitePool::connect_with(options).await?;
+    sqlx::migrate!()
+        .run(&pool)
+        .await
+        .map_err(|e| sqlx::Error::Migrate(Box::new(e)))?;
+    Ok(pool)
 }
 
 #[cfg(test)]
 pub(crate) async fn routes_test() -> axum_test::TestServer {
-    let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
-    sqlx::migrate!().run(&pool).await.unwrap();
-    use axum_test::Transport;
+    let pool = pool("sqlite::memory:").await.unwrap();
     let _ = tracing_subscriber::fmt()
         .with_max_level(tracing::Level::DEBUG)
         .try_init();
@@ -59,9 +71,9 @@ pub(crate) async fn routes_test() -> axum_test::TestServer {
     let config = axum_test::TestServerConfig {
         save_cookies: true,
         expect_success_by_default: true,
-        transport: Some(Transport::MockHttp),
+        transport: Some(axum_test::Transport::MockHttp),
         ..Default::default()
     };
 
     axum_test::TestServer::new_with_config(app, config).unwrap()
-}
\ No newline at end of file
+}
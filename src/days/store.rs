@@ -0,0 +1,301 @@
+//! Typed access to the `orders` and `regions` tables of Day 18, and to the
+//! orders Day 13 keeps on their own, with the reports both days share.
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::{SqliteConnection, SqlitePool};
+use ulid::Ulid;
+
+mod format;
+mod ingest;
+mod list;
+mod series;
+
+pub(super) use format::{csv_response, Format};
+pub(super) use ingest::{ingest, IngestQuery, Report};
+pub(super) use list::{OrderQuery, Page, RegionQuery};
+pub(super) use series::{series, trend, SeriesQuery, TrendQuery};
+
+/// A table of orders. Day 13 has no regions for its orders to reference, so
+/// it keeps them apart from Day 18's.
+#[derive(Clone, Copy)]
+pub(super) enum Orders {
+    Day13,
+    Day18,
+}
+
+impl Orders {
+    pub(super) fn table(self) -> &'static str {
+        match self {
+            Orders::Day13 => "day13_orders",
+            Orders::Day18 => "orders",
+        }
+    }
+
+    /// Empties the tables the day uses, the schema stays as migrated.
+    pub(super) async fn clear(self, pool: &SqlitePool) -> sqlx::Result<()> {
+        let mut tx = pool.begin().await?;
+        sqlx::query(&format!("DELETE FROM {}", self.table()))
+            .execute(&mut *tx)
+            .await?;
+        if let Orders::Day18 = self {
+            sqlx::query("DELETE FROM regions").execute(&mut *tx).await?;
+        }
+        tx.commit().await
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, sqlx::FromRow)]
+pub(super) struct Order {
+    pub(super) id: i32,
+    pub(super) region_id: i32,
+    pub(super) gift_name: String,
+    pub(super) quantity: i32,
+    /// `None` only for orders stored before there was a time to store.
+    #[serde(default)]
+    pub(super) ordered_at: Option<DateTime<Utc>>,
+    /// A ULID the order was submitted under, its timestamp is when the order
+    /// was placed.
+    #[serde(
+        default,
+        deserialize_with = "ulid",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(super) ulid: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, sqlx::FromRow)]
+pub(super) struct Region {
+    pub(super) id: i32,
+    pub(super) name: String,
+}
+
+/// The fields a `PATCH` may change, the rest are kept.
+#[derive(serde::Deserialize)]
+pub(super) struct OrderPatch {
+    region_id: Option<i32>,
+    gift_name: Option<String>,
+    quantity: Option<i32>,
+    ordered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct RegionPatch {
+    name: Option<String>,
+}
+
+impl OrderPatch {
+    fn apply(self, order: Order) -> Order {
+        Order {
+            id: order.id,
+            region_id: self.region_id.unwrap_or(order.region_id),
+            gift_name: self.gift_name.unwrap_or(order.gift_name),
+            quantity: self.quantity.unwrap_or(order.quantity),
+            ordered_at: self.ordered_at.or(order.ordered_at),
+            ulid: order.ulid,
+        }
+    }
+}
+
+impl RegionPatch {
+    fn apply(self, region: Region) -> Region {
+        Region {
+            id: region.id,
+            name: self.name.unwrap_or(region.name),
+        }
+    }
+}
+
+impl Order {
+    /// Fills in when the order was placed, unless given: from its ULID if it
+    /// has one, else `now`.
+    pub(super) fn stamp(&mut self, now: DateTime<Utc>) {
+        let from_ulid = self
+            .ulid
+            .as_deref()
+            .and_then(|ulid| ulid.parse::<Ulid>().ok())
+            .and_then(|ulid| DateTime::from_timestamp_millis(ulid.timestamp_ms() as i64));
+        self.ordered_at = self.ordered_at.or(from_ulid).or(Some(now));
+    }
+
+    pub(super) async fn find(pool: &SqlitePool, id: i32) -> sqlx::Result<Order> {
+        let mut conn = pool.acquire().await?;
+        Order::find_in(&mut conn, id).await
+    }
+
+    async fn find_in(conn: &mut SqliteConnection, id: i32) -> sqlx::Result<Order> {
+        sqlx::query_as(
+            "SELECT id, region_id, gift_name, quantity, ordered_at, ulid FROM orders WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_one(conn)
+        .await
+    }
+
+    /// Stores the order under its id, `true` if there was none before.
+    pub(super) async fn save(&self, pool: &SqlitePool) -> sqlx::Result<bool> {
+        let mut tx = pool.begin().await?;
+        let created = !exists(&mut tx, "orders", self.id).await?;
+        self.save_in(&mut tx).await?;
+        tx.commit().await?;
+        Ok(created)
+    }
+
+    async fn save_in(&self, conn: &mut SqliteConnection) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT INTO orders (id, region_id, gift_name, quantity, ordered_at, ulid)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET
+                region_id = excluded.region_id,
+                gift_name = excluded.gift_name,
+                quantity = excluded.quantity,
+                ordered_at = excluded.ordered_at,
+                ulid = excluded.ulid",
+        )
+        .bind(self.id)
+        .bind(self.region_id)
+        .bind(&self.gift_name)
+        .bind(self.quantity)
+        .bind(self.ordered_at)
+        .bind(&self.ulid)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub(super) async fn patch(
+        pool: &SqlitePool,
+        id: i32,
+        patch: OrderPatch,
+    ) -> sqlx::Result<Order> {
+        let mut tx = pool.begin().await?;
+        let order = patch.apply(Order::find_in(&mut tx, id).await?);
+        order.save_in(&mut tx).await?;
+        tx.commit().await?;
+        Ok(order)
+    }
+
+    /// `false` if there was no such order.
+    pub(super) async fn delete(pool: &SqlitePool, id: i32) -> sqlx::Result<bool> {
+        let deleted = sqlx::query("DELETE FROM orders WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+}
+
+impl Region {
+    pub(super) async fn find(pool: &SqlitePool, id: i32) -> sqlx::Result<Region> {
+        let mut conn = pool.acquire().await?;
+        Region::find_in(&mut conn, id).await
+    }
+
+    async fn find_in(conn: &mut SqliteConnection, id: i32) -> sqlx::Result<Region> {
+        sqlx::query_as("SELECT id, name FROM regions WHERE id = ?")
+            .bind(id)
+            .fetch_one(conn)
+            .await
+    }
+
+    /// Stores the region under its id, `true` if there was none before.
+    pub(super) async fn save(&self, pool: &SqlitePool) -> sqlx::Result<bool> {
+        let mut tx = pool.begin().await?;
+        let created = !exists(&mut tx, "regions", self.id).await?;
+        self.save_in(&mut tx).await?;
+        tx.commit().await?;
+        Ok(created)
+    }
+
+    async fn save_in(&self, conn: &mut SqliteConnection) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT INTO regions (id, name) VALUES (?, ?)
+            ON CONFLICT (id) DO UPDATE SET name = excluded.name",
+        )
+        .bind(self.id)
+        .bind(&self.name)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Adds new regions, failing on an id that's taken.
+    pub(super) async fn insert(&self, pool: &SqlitePool) -> sqlx::Result<()> {
+        sqlx::query("INSERT INTO regions (id, name) VALUES (?, ?)")
+            .bind(self.id)
+            .bind(&self.name)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub(super) async fn patch(
+        pool: &SqlitePool,
+        id: i32,
+        patch: RegionPatch,
+    ) -> sqlx::Result<Region> {
+        let mut tx = pool.begin().await?;
+        let region = patch.apply(Region::find_in(&mut tx, id).await?);
+        region.save_in(&mut tx).await?;
+        tx.commit().await?;
+        Ok(region)
+    }
+
+    /// `false` if there was no such region.
+    pub(super) async fn delete(pool: &SqlitePool, id: i32) -> sqlx::Result<bool> {
+        let deleted = sqlx::query("DELETE FROM regions WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+}
+
+/// Checks a ULID where it's read, so a bad one is reported with its row.
+pub(super) fn ulid<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    let ulid = Option::<String>::deserialize(d)?;
+    ulid.map(|ulid| {
+        ulid.parse::<Ulid>()
+            .map(|ulid| ulid.to_string())
+            .map_err(|e| serde::de::Error::custom(format!("invalid ulid: {e}")))
+    })
+    .transpose()
+}
+
+/// `table` is one of ours, never user input.
+async fn exists(conn: &mut SqliteConnection, table: &str, id: i32) -> sqlx::Result<bool> {
+    let found = sqlx::query(&format!("SELECT 1 FROM {table} WHERE id = ?"))
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+    Ok(found.is_some())
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::SqlitePool;
+
+    #[tokio::test]
+    async fn orders_without_a_region_move_to_day_13() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        for sql in [
+            include_str!("../../migrations/20241006083056_day_13.sql"),
+            include_str!("../../migrations/20241011105553_day_18.sql"),
+            "INSERT INTO regions VALUES (1, 'North Pole');
+            INSERT INTO orders VALUES (1, 1, 'Doll', 2), (2, 7, 'Toy Train', 5), (3, 1, '', 1);",
+            include_str!("../../migrations/20261018140000_orders_constraints.sql"),
+        ] {
+            sqlx::raw_sql(sql).execute(&pool).await.unwrap();
+        }
+        let day18: Vec<(i32, String)> = sqlx::query_as("SELECT id, gift_name FROM orders")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(day18, [(1, String::from("Doll"))]);
+        let day13: Vec<(i32, i32, String, i32)> = sqlx::query_as("SELECT * FROM day13_orders")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(day13, [(2, 7, String::from("Toy Train"), 5)]);
+    }
+}
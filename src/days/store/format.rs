@@ -0,0 +1,65 @@
+//! How reports over the orders are sent, as JSON or, with `?format=csv`,
+//! as CSV.
+
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::error::AppError;
+
+#[derive(Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Format {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl Format {
+    /// One CSV column per field of `T`, so `T` has to be flat.
+    pub(crate) fn respond<T: serde::Serialize>(self, rows: &[T]) -> Result<Response, AppError> {
+        match self {
+            Format::Json => Ok(Json(rows).into_response()),
+            Format::Csv => {
+                let mut csv = csv::Writer::from_writer(vec![]);
+                for row in rows {
+                    csv.serialize(row)
+                        .map_err(|e| AppError::Internal(e.to_string()))?;
+                }
+                csv_response(csv)
+            }
+        }
+    }
+}
+
+/// Rewrites `csv` with every cell a spreadsheet would run as a formula
+/// quoted by a leading `'`. Numbers, negative ones included, are left alone.
+pub(crate) fn csv_response(csv: csv::Writer<Vec<u8>>) -> Result<Response, AppError> {
+    let written = csv
+        .into_inner()
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(written.as_slice());
+    let mut csv = csv::Writer::from_writer(vec![]);
+    for record in reader.records() {
+        let record = record.map_err(|e| AppError::Internal(e.to_string()))?;
+        let escaped = record.iter().map(|cell| {
+            let formula =
+                cell.starts_with(['=', '+', '-', '@', '\t', '\r']) && cell.parse::<f64>().is_err();
+            if formula {
+                format!("'{cell}")
+            } else {
+                cell.to_string()
+            }
+        });
+        csv.write_record(escaped)
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+    }
+    let body = csv
+        .into_inner()
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(([(header::CONTENT_TYPE, "text/csv; charset=utf-8")], body).into_response())
+}
@@ -0,0 +1,283 @@
+use chrono::Utc;
+use futures_util::StreamExt;
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection, SqlitePool};
+
+use super::{Order, Orders};
+use crate::{
+    error::AppError,
+    records::{Errors, Records},
+};
+
+/// Orders written per `INSERT`, six bound values each.
+const BATCH: usize = 200;
+/// Conflicting ids reported back at most.
+const MAX_IDS: usize = 100;
+/// Orders in one upload, all held in memory until the upload is written.
+const MAX_ORDERS: usize = 100_000;
+
+/// What to do with an order whose id is already taken.
+#[derive(Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OnConflict {
+    /// Fail the whole upload, nothing is written.
+    #[default]
+    Reject,
+    /// Keep the stored order and carry on.
+    Skip,
+    /// Replace the stored order.
+    Upsert,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct IngestQuery {
+    #[serde(default)]
+    pub(crate) on_conflict: OnConflict,
+}
+
+#[derive(Debug, Default, PartialEq, serde::Serialize)]
+pub(crate) struct Report {
+    inserted: usize,
+    updated: usize,
+    skipped: usize,
+    /// Conflicts when rejecting, which roll the upload back.
+    failed: usize,
+    skipped_ids: Vec<i32>,
+    failed_ids: Vec<i32>,
+}
+
+impl Report {
+    fn conflicts(&mut self, on_conflict: OnConflict, ids: Vec<i32>) {
+        let (count, reported) = match on_conflict {
+            OnConflict::Skip => (&mut self.skipped, &mut self.skipped_ids),
+            _ => (&mut self.failed, &mut self.failed_ids),
+        };
+        *count += ids.len();
+        let room = MAX_IDS.saturating_sub(reported.len());
+        reported.extend(ids.into_iter().take(room));
+    }
+}
+
+/// Writes an upload of orders in one transaction, a batch at a time. Rows
+/// that can't be read, or conflicts when rejecting, roll all of it back.
+/// Orders without a time of their own are stamped with the time of upload.
+///
+/// The whole upload, at most [`MAX_ORDERS`], is read and checked before the
+/// transaction starts, so a slow client never holds the write lock.
+pub(crate) async fn ingest(
+    pool: &SqlitePool,
+    orders: Orders,
+    records: Records,
+    on_conflict: OnConflict,
+) -> Result<Report, AppError> {
+    let table = orders.table();
+    let now = Utc::now();
+    let mut errors = Errors::default();
+    let mut upload = Vec::new();
+
+    let mut decoded = std::pin::pin!(records.decode::<Order>());
+    while let Some(order) = decoded.next().await {
+        match order {
+            // past a broken row the rest is only checked, to report every error
+            Ok(_) if upload.len() == MAX_ORDERS => {
+                return Err(AppError::bad_request(format!(
+                    "an upload holds at most {MAX_ORDERS} orders"
+                )));
+            }
+            Ok(mut order) if errors.is_empty() => {
+                order.stamp(now);
+                upload.push(order);
+            }
+            Ok(_) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+    errors.into_result()?;
+
+    let mut tx = pool.begin().await?;
+    let mut report = Report::default();
+    for batch in upload.chunks(BATCH) {
+        write(&mut tx, table, batch, on_conflict, &mut report).await?;
+    }
+
+    if report.failed > 0 {
+        let detail = format!("{} orders have ids that are already taken", report.failed);
+        let report = Report {
+            inserted: 0,
+            ..report
+        };
+        return Err(AppError::conflict(detail, serde_json::to_value(report)?));
+    }
+    tx.commit().await?;
+    Ok(report)
+}
+
+async fn write(
+    conn: &mut SqliteConnection,
+    table: &str,
+    batch: &[Order],
+    on_conflict: OnConflict,
+    report: &mut Report,
+) -> Result<(), AppError> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    let mut existing = Vec::new();
+    if let OnConflict::Upsert = on_conflict {
+        let mut query = QueryBuilder::<Sqlite>::new(format!("SELECT id FROM {table} WHERE id IN "));
+        query.push_tuples(batch, |mut row, order| {
+            row.push_bind(order.id);
+        });
+        existing = query.build_query_scalar().fetch_all(&mut *conn).await?;
+    }
+
+    let mut query = QueryBuilder::<Sqlite>::new(format!(
+        "INSERT INTO {table} (id, region_id, gift_name, quantity, ordered_at, ulid) "
+    ));
+    query.push_values(batch, |mut row, order| {
+        row.push_bind(order.id)
+            .push_bind(order.region_id)
+            .push_bind(&order.gift_name)
+            .push_bind(order.quantity)
+            .push_bind(order.ordered_at)
+            .push_bind(&order.ulid);
+    });
+    match on_conflict {
+        OnConflict::Upsert => query.push(
+            " ON CONFLICT (id) DO UPDATE SET
+                region_id = excluded.region_id,
+                gift_name = excluded.gift_name,
+                quantity = excluded.quantity,
+                ordered_at = excluded.ordered_at,
+                ulid = excluded.ulid",
+        ),
+        _ => query.push(" ON CONFLICT (id) DO NOTHING"),
+    };
+    query.push(" RETURNING id");
+    let mut written: Vec<i32> = query.build_query_scalar().fetch_all(&mut *conn).await?;
+
+    if let OnConflict::Upsert = on_conflict {
+        // a repeated id within the batch updates what the batch itself inserted
+        let mut seen = existing;
+        for order in batch {
+            if seen.contains(&order.id) {
+                report.updated += 1;
+            } else {
+                report.inserted += 1;
+                seen.push(order.id);
+            }
+        }
+        return Ok(());
+    }
+
+    // whatever wasn't written conflicted, repeats within the batch included
+    let mut conflicts = Vec::new();
+    for order in batch {
+        match written.iter().position(|id| *id == order.id) {
+            Some(i) => {
+                written.swap_remove(i);
+                report.inserted += 1;
+            }
+            None => conflicts.push(order.id),
+        }
+    }
+    report.conflicts(on_conflict, conflicts);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use crate::days::routes_test;
+
+    fn order(id: i32, quantity: i32) -> serde_json::Value {
+        json!({"id": id, "region_id": 1, "gift_name": "Doll", "quantity": quantity})
+    }
+
+    #[tokio::test]
+    async fn on_conflict() {
+        let server = routes_test().await;
+        server.post("/13/reset").await.assert_status_ok();
+        server
+            .post("/13/orders")
+            .json(&json!([order(1, 5), order(2, 3)]))
+            .await
+            .assert_json(&json!({
+                "inserted": 2, "updated": 0, "skipped": 0, "failed": 0,
+                "skipped_ids": [], "failed_ids": []
+            }));
+
+        let res = server
+            .post("/13/orders")
+            .json(&json!([order(3, 1), order(2, 1), order(3, 1)]))
+            .expect_failure()
+            .await;
+        res.assert_status(StatusCode::CONFLICT);
+        res.assert_json_contains(&json!({
+            "status": 409,
+            "inserted": 0,
+            "failed": 2,
+            "failed_ids": [2, 3]
+        }));
+        // rolled back, order 3 wasn't kept
+        server
+            .get("/13/orders/total")
+            .await
+            .assert_json(&json!({"total": 8}));
+
+        server
+            .post("/13/orders?on_conflict=skip")
+            .json(&json!([order(3, 1), order(2, 1)]))
+            .await
+            .assert_json_contains(&json!({"inserted": 1, "skipped": 1, "skipped_ids": [2]}));
+        server
+            .post("/13/orders?on_conflict=upsert")
+            .json(&json!([order(1, 10), order(4, 1), order(4, 2)]))
+            .await
+            .assert_json_contains(&json!({"inserted": 1, "updated": 2}));
+        server
+            .get("/13/orders/total")
+            .await
+            .assert_json(&json!({"total": 16}));
+
+        server
+            .post("/13/orders?on_conflict=overwrite")
+            .json(&json!([]))
+            .expect_failure()
+            .await
+            .assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn batches_and_broken_rows() {
+        let server = routes_test().await;
+        server.post("/18/reset").await.assert_status_ok();
+        server
+            .post("/18/regions")
+            .json(&json!([{"id": 1, "name": "North Pole"}]))
+            .await;
+        let orders: Vec<_> = (1..=450).map(|id| order(id, 1)).collect();
+        server
+            .post("/18/orders")
+            .json(&orders)
+            .await
+            .assert_json_contains(&json!({"inserted": 450}));
+
+        // a bad row in a later batch undoes the earlier ones too
+        let mut orders: Vec<_> = (1000..1450).map(|id| order(id, 1)).collect();
+        orders.push(json!({"id": "x"}));
+        server
+            .post("/18/orders")
+            .json(&orders)
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        server
+            .post("/18/orders?on_conflict=skip")
+            .json(&json!([order(1000, 1)]))
+            .await
+            .assert_json_contains(&json!({"inserted": 1}));
+    }
+}
@@ -0,0 +1,169 @@
+use base64::prelude::*;
+use sqlx::{sqlite::SqliteRow, FromRow, QueryBuilder, Sqlite, SqlitePool};
+
+use super::{Order, Region};
+use crate::error::AppError;
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 500;
+
+/// Filters, order and page of `GET /orders`.
+#[derive(serde::Deserialize)]
+pub(crate) struct OrderQuery {
+    region_id: Option<i32>,
+    gift_name: Option<String>,
+    min_quantity: Option<i32>,
+    max_quantity: Option<i32>,
+    /// A column, `-` first for descending, like `-quantity`. By id by default.
+    sort: Option<String>,
+    cursor: Option<String>,
+    limit: Option<u32>,
+}
+
+/// Filters, order and page of `GET /regions`.
+#[derive(serde::Deserialize)]
+pub(crate) struct RegionQuery {
+    name: Option<String>,
+    sort: Option<String>,
+    cursor: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(serde::Serialize)]
+pub(crate) struct Page<T> {
+    items: Vec<T>,
+    /// Pass as `cursor` for the next page, `None` on the last one.
+    next_cursor: Option<String>,
+}
+
+/// Where the previous page stopped: the sort key and id of its last item.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Cursor {
+    sort: String,
+    key: serde_json::Value,
+    id: i32,
+}
+
+struct Sort {
+    column: &'static str,
+    descending: bool,
+}
+
+impl Sort {
+    fn parse(spec: Option<&str>, columns: &[&'static str]) -> Result<Sort, AppError> {
+        let spec = spec.unwrap_or("id");
+        let (name, descending) = match spec.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (spec, false),
+        };
+        let column = columns
+            .iter()
+            .find(|c| **c == name)
+            .ok_or_else(|| AppError::bad_request(format!("can't sort by {name}")))?;
+        Ok(Sort { column, descending })
+    }
+
+    fn spec(&self) -> String {
+        let sign = if self.descending { "-" } else { "" };
+        format!("{sign}{}", self.column)
+    }
+}
+
+impl Order {
+    pub(crate) async fn list(pool: &SqlitePool, q: &OrderQuery) -> Result<Page<Order>, AppError> {
+        let sort = Sort::parse(
+            q.sort.as_deref(),
+            &["id", "region_id", "gift_name", "quantity"],
+        )?;
+        let mut query = QueryBuilder::new(
+            "SELECT id, region_id, gift_name, quantity, ordered_at, ulid FROM orders WHERE 1 = 1",
+        );
+        if let Some(region_id) = q.region_id {
+            query.push(" AND region_id = ").push_bind(region_id);
+        }
+        if let Some(gift_name) = &q.gift_name {
+            query.push(" AND gift_name = ").push_bind(gift_name.clone());
+        }
+        if let Some(min) = q.min_quantity {
+            query.push(" AND quantity >= ").push_bind(min);
+        }
+        if let Some(max) = q.max_quantity {
+            query.push(" AND quantity <= ").push_bind(max);
+        }
+        page(pool, query, sort, q.cursor.as_deref(), q.limit).await
+    }
+}
+
+impl Region {
+    pub(crate) async fn list(pool: &SqlitePool, q: &RegionQuery) -> Result<Page<Region>, AppError> {
+        let sort = Sort::parse(q.sort.as_deref(), &["id", "name"])?;
+        let mut query = QueryBuilder::new("SELECT id, name FROM regions WHERE 1 = 1");
+        if let Some(name) = &q.name {
+            query.push(" AND name = ").push_bind(name.clone());
+        }
+        page(pool, query, sort, q.cursor.as_deref(), q.limit).await
+    }
+}
+
+/// Finishes a filtered `query` with keyset pagination, ties broken by id.
+async fn page<'a, T>(
+    pool: &SqlitePool,
+    mut query: QueryBuilder<'a, Sqlite>,
+    sort: Sort,
+    cursor: Option<&str>,
+    limit: Option<u32>,
+) -> Result<Page<T>, AppError>
+where
+    T: for<'r> FromRow<'r, SqliteRow> + serde::Serialize + Send + Unpin,
+{
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let column = sort.column;
+    let (direction, past) = if sort.descending {
+        ("DESC", "<")
+    } else {
+        ("ASC", ">")
+    };
+
+    if let Some(cursor) = cursor {
+        let cursor = decode(cursor)?;
+        if cursor.sort != sort.spec() {
+            return Err(AppError::bad_request("the cursor is for another sort"));
+        }
+        query.push(format!(" AND ({column}, id) {past} ("));
+        match cursor.key {
+            serde_json::Value::String(key) => query.push_bind(key),
+            serde_json::Value::Number(key) if key.is_i64() => query.push_bind(key.as_i64()),
+            _ => return Err(AppError::bad_request("invalid cursor")),
+        };
+        query.push(", ").push_bind(cursor.id).push(")");
+    }
+    query
+        .push(format!(
+            " ORDER BY {column} {direction}, id {direction} LIMIT "
+        ))
+        .push_bind(limit + 1);
+
+    let mut items: Vec<T> = query.build_query_as().fetch_all(pool).await?;
+    let next_cursor = if items.len() > limit as usize {
+        items.truncate(limit as usize);
+        let last = serde_json::to_value(items.last())?;
+        Some(encode(&Cursor {
+            sort: sort.spec(),
+            key: last[column].clone(),
+            id: serde_json::from_value(last["id"].clone())?,
+        })?)
+    } else {
+        None
+    };
+
+    Ok(Page { items, next_cursor })
+}
+
+fn encode(cursor: &Cursor) -> Result<String, AppError> {
+    Ok(BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(cursor)?))
+}
+
+fn decode(cursor: &str) -> Result<Cursor, AppError> {
+    serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(cursor)?)
+        .map_err(|_| AppError::bad_request("invalid cursor"))
+}
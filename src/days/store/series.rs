@@ -0,0 +1,332 @@
+//! Order totals over time, from when each order was placed. Orders stored
+//! before there were times are left out.
+
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+
+use super::{Format, Orders};
+use crate::error::AppError;
+
+/// Buckets averaged over by default, a week of days.
+const DEFAULT_WINDOW: u32 = 7;
+const MAX_WINDOW: u32 = 366;
+/// Buckets a trend can span, empty ones included, a bit over a year of hours.
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Bucket {
+    Hour,
+    #[default]
+    Day,
+    /// Starting on Monday.
+    Week,
+}
+
+impl Bucket {
+    /// The start of the bucket `ordered_at` falls in, in UTC.
+    fn start(self) -> &'static str {
+        match self {
+            Bucket::Hour => "strftime('%Y-%m-%dT%H:00:00Z', ordered_at)",
+            Bucket::Day => "strftime('%Y-%m-%dT00:00:00Z', ordered_at)",
+            Bucket::Week => "strftime('%Y-%m-%dT00:00:00Z', ordered_at, 'weekday 0', '-6 days')",
+        }
+    }
+
+    /// A modifier moving the start of a bucket to that of the next one.
+    fn step(self) -> &'static str {
+        match self {
+            Bucket::Hour => "'+1 hour'",
+            Bucket::Day => "'+1 day'",
+            Bucket::Week => "'+7 days'",
+        }
+    }
+}
+
+#[derive(Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum By {
+    Region,
+    Gift,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct SeriesQuery {
+    #[serde(default)]
+    bucket: Bucket,
+    /// A total per region or gift in each bucket, else one for all orders.
+    /// Day 13 has no regions, so its orders are grouped by region id.
+    by: Option<By>,
+    /// Orders placed at or after.
+    from: Option<DateTime<Utc>>,
+    /// Orders placed before.
+    to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow)]
+struct Point {
+    bucket: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    total: i64,
+}
+
+/// Limits `query`, which already has a `WHERE`, to orders placed in the range.
+/// Times are only ever written as RFC 3339 in UTC, which sorts as text, so
+/// the column is compared as is and `orders_ordered_at` can be used.
+fn placed_between(
+    query: &mut QueryBuilder<'_, Sqlite>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) {
+    query.push(" AND ordered_at IS NOT NULL");
+    if let Some(from) = from {
+        query.push(" AND ordered_at >= ").push_bind(from);
+    }
+    if let Some(to) = to {
+        query.push(" AND ordered_at < ").push_bind(to);
+    }
+}
+
+/// Totals per bucket with orders in it.
+pub(crate) async fn series(
+    pool: &SqlitePool,
+    orders: Orders,
+    q: SeriesQuery,
+) -> Result<Response, AppError> {
+    let table = orders.table();
+    let (key, join) = match (q.by, orders) {
+        (Some(By::Region), Orders::Day18) => (
+            "regions.name",
+            " JOIN regions ON regions.id = orders.region_id",
+        ),
+        (Some(By::Region), Orders::Day13) => ("CAST(region_id AS TEXT)", ""),
+        (Some(By::Gift), _) => ("gift_name", ""),
+        (None, _) => ("NULL", ""),
+    };
+    let mut query = QueryBuilder::<Sqlite>::new(format!(
+        "SELECT {} AS bucket, {key} AS key, SUM(quantity) AS total
+        FROM {table}{join}
+        WHERE 1 = 1",
+        q.bucket.start()
+    ));
+    placed_between(&mut query, q.from, q.to);
+    query.push(" GROUP BY bucket, key ORDER BY bucket ASC, key ASC");
+    let points: Vec<Point> = query.build_query_as().fetch_all(pool).await?;
+    q.format.respond(&points)
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct TrendQuery {
+    #[serde(default)]
+    bucket: Bucket,
+    /// How many buckets, up to and including each one, are averaged.
+    window: Option<u32>,
+    region_id: Option<i32>,
+    gift_name: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow)]
+struct TrendPoint {
+    bucket: String,
+    total: i64,
+    moving_average: f64,
+}
+
+/// Totals per bucket with a moving average over the last `window` of them.
+/// Buckets between the first and last order count, those without orders as 0,
+/// up to [`MAX_BUCKETS`] of them.
+pub(crate) async fn trend(
+    pool: &SqlitePool,
+    orders: Orders,
+    q: TrendQuery,
+) -> Result<Response, AppError> {
+    let window = q.window.unwrap_or(DEFAULT_WINDOW);
+    if !(1..=MAX_WINDOW).contains(&window) {
+        return Err(AppError::bad_request(format!(
+            "window must be between 1 and {MAX_WINDOW}"
+        )));
+    }
+
+    let mut query = QueryBuilder::<Sqlite>::new(format!(
+        "WITH RECURSIVE totals AS (
+          SELECT {} AS bucket, SUM(quantity) AS total FROM {} WHERE 1 = 1",
+        q.bucket.start(),
+        orders.table()
+    ));
+    placed_between(&mut query, q.from, q.to);
+    if let Some(region_id) = q.region_id {
+        query.push(" AND region_id = ").push_bind(region_id);
+    }
+    if let Some(gift_name) = q.gift_name {
+        query.push(" AND gift_name = ").push_bind(gift_name);
+    }
+    // `window` is checked above, the frame can't take a bound value
+    query.push(format!(
+        " GROUP BY bucket
+        ), span (bucket, n) AS (
+          SELECT MIN(bucket), 1 FROM totals HAVING COUNT(*) > 0
+          UNION ALL
+          SELECT strftime('%Y-%m-%dT%H:%M:%SZ', bucket, {step}), n + 1 FROM span
+          WHERE bucket < (SELECT MAX(bucket) FROM totals) AND n <= {MAX_BUCKETS}
+        )
+        SELECT
+          span.bucket,
+          COALESCE(totals.total, 0) AS total,
+          AVG(COALESCE(totals.total, 0)) OVER (
+            ORDER BY span.bucket ROWS BETWEEN {preceding} PRECEDING AND CURRENT ROW
+          ) AS moving_average
+        FROM span LEFT JOIN totals ON totals.bucket = span.bucket
+        ORDER BY span.bucket ASC",
+        step = q.bucket.step(),
+        preceding = window - 1,
+    ));
+    let points: Vec<TrendPoint> = query.build_query_as().fetch_all(pool).await?;
+    if points.len() > MAX_BUCKETS {
+        return Err(AppError::bad_request(format!(
+            "the orders span more than {MAX_BUCKETS} buckets, narrow them with from and to"
+        )));
+    }
+    q.format.respond(&points)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::days::routes_test;
+
+    async fn seeded() -> axum_test::TestServer {
+        let server = routes_test().await;
+        server.post("/18/reset").await.assert_status_ok();
+        server
+            .post("/18/regions")
+            .json(&json!([{"id":1,"name":"North Pole"},{"id":2,"name":"South Pole"}]))
+            .await;
+        server
+            .post("/18/orders")
+            .json(&json!([
+              {"id":1,"region_id":1,"gift_name":"Doll","quantity":4,"ordered_at":"2024-12-23T09:15:00Z"},
+              {"id":2,"region_id":2,"gift_name":"Doll","quantity":2,"ordered_at":"2024-12-23T09:45:00Z"},
+              {"id":3,"region_id":1,"gift_name":"Drone","quantity":6,"ordered_at":"2024-12-23T11:00:00Z"},
+              {"id":4,"region_id":1,"gift_name":"Doll","quantity":3,"ordered_at":"2024-12-26T08:00:00+01:00"}
+            ]))
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn buckets() {
+        let server = seeded().await;
+        server
+            .get("/18/orders/series?bucket=hour")
+            .await
+            .assert_json(&json!([
+              {"bucket": "2024-12-23T09:00:00Z", "total": 6},
+              {"bucket": "2024-12-23T11:00:00Z", "total": 6},
+              {"bucket": "2024-12-26T07:00:00Z", "total": 3}
+            ]));
+        server
+            .get("/18/orders/series?by=region")
+            .await
+            .assert_json(&json!([
+              {"bucket": "2024-12-23T00:00:00Z", "key": "North Pole", "total": 10},
+              {"bucket": "2024-12-23T00:00:00Z", "key": "South Pole", "total": 2},
+              {"bucket": "2024-12-26T00:00:00Z", "key": "North Pole", "total": 3}
+            ]));
+        // the 23rd is a Monday, the 26th in the same week
+        server
+            .get("/18/orders/series?bucket=week&by=gift&from=2024-12-23T10:00:00Z")
+            .await
+            .assert_json(&json!([
+              {"bucket": "2024-12-23T00:00:00Z", "key": "Doll", "total": 3},
+              {"bucket": "2024-12-23T00:00:00Z", "key": "Drone", "total": 6}
+            ]));
+        server
+            .get("/18/orders/series?to=2024-12-24T00:00:00Z&format=csv")
+            .await
+            .assert_text("bucket,total\n2024-12-23T00:00:00Z,12\n");
+    }
+
+    #[tokio::test]
+    async fn trend() {
+        let server = seeded().await;
+        server
+            .get("/18/orders/trend?window=2")
+            .await
+            .assert_json(&json!([
+              {"bucket": "2024-12-23T00:00:00Z", "total": 12, "moving_average": 12.0},
+              {"bucket": "2024-12-24T00:00:00Z", "total": 0, "moving_average": 6.0},
+              {"bucket": "2024-12-25T00:00:00Z", "total": 0, "moving_average": 0.0},
+              {"bucket": "2024-12-26T00:00:00Z", "total": 3, "moving_average": 1.5}
+            ]));
+        let points = server
+            .get("/18/orders/trend?gift_name=Drone&region_id=1")
+            .await
+            .json::<Vec<serde_json::Value>>();
+        assert_eq!(points.len(), 1);
+        server
+            .get("/18/orders/trend?from=2025-01-01T00:00:00Z")
+            .await
+            .assert_json(&json!([]));
+        // a year and a half of hours is too long a span, unless narrowed
+        server
+            .put("/18/orders/9")
+            .json(&json!({"region_id": 1, "gift_name": "Doll", "quantity": 1, "ordered_at": "2026-06-01T00:00:00Z"}))
+            .await
+            .assert_status(axum::http::StatusCode::CREATED);
+        server
+            .get("/18/orders/trend?bucket=hour")
+            .expect_failure()
+            .await
+            .assert_status_bad_request();
+        let points = server
+            .get("/18/orders/trend?bucket=hour&to=2025-01-01T00:00:00Z")
+            .await
+            .json::<Vec<serde_json::Value>>();
+        assert_eq!(points.len(), 71);
+        for bad in ["window=0", "window=1000", "bucket=year"] {
+            server
+                .get(&format!("/18/orders/trend?{bad}"))
+                .expect_failure()
+                .await
+                .assert_status_bad_request();
+        }
+    }
+
+    #[tokio::test]
+    async fn timestamps_from_ulids_and_upload() {
+        let server = seeded().await;
+        let ulid = ulid::Ulid::from_parts(1_734_955_200_000, 7).to_string();
+        server
+            .put("/18/orders/5")
+            .json(&json!({"region_id": 2, "gift_name": "Doll", "quantity": 1, "ulid": ulid}))
+            .await
+            .assert_json_contains(&json!({"ordered_at": "2024-12-23T12:00:00Z", "ulid": ulid}));
+        server
+            .put("/18/orders/6")
+            .json(&json!({"region_id": 2, "gift_name": "Doll", "quantity": 1, "ulid": "nope"}))
+            .expect_failure()
+            .await
+            .assert_status_unprocessable_entity();
+
+        let before = chrono::Utc::now();
+        server
+            .post("/18/orders")
+            .text("id,region_id,gift_name,quantity\n7,1,Doll,1\n")
+            .content_type("text/csv")
+            .await
+            .assert_status_ok();
+        let order = server.get("/18/orders/7").await.json::<serde_json::Value>();
+        let ordered_at: chrono::DateTime<chrono::Utc> =
+            serde_json::from_value(order["ordered_at"].clone()).unwrap();
+        assert!(ordered_at >= before - chrono::Duration::seconds(1));
+    }
+}
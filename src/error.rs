@@ -0,0 +1,256 @@
+use axum::{
+    async_trait,
+    extract::{multipart::MultipartError, rejection::JsonRejection, FromRequest, Request},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use sqlx::error::{DatabaseError, ErrorKind};
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum AppError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    RateLimited(String),
+    /// Well-formed input that fails validation.
+    #[error("{0}")]
+    Unprocessable(String),
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+    #[error("{detail}")]
+    Conflict {
+        detail: String,
+        /// Extra members for the problem body.
+        extensions: serde_json::Map<String, serde_json::Value>,
+    },
+    /// Records of an upload that couldn't be read.
+    #[error("{count} rows could not be read")]
+    Rows {
+        count: usize,
+        rows: Vec<crate::records::RowError>,
+    },
+    #[error("{0}")]
+    BadGateway(String),
+    #[error("{0}")]
+    Internal(String),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error(transparent)]
+    Upstream(#[from] reqwest::Error),
+    #[error(transparent)]
+    Multipart(#[from] MultipartError),
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+}
+
+impl AppError {
+    pub(crate) fn bad_request(detail: impl ToString) -> Self {
+        AppError::BadRequest(detail.to_string())
+    }
+
+    /// A 409, with the members of `extensions` added to the problem body.
+    pub(crate) fn conflict(detail: impl ToString, extensions: serde_json::Value) -> Self {
+        let serde_json::Value::Object(extensions) = extensions else {
+            return AppError::Conflict {
+                detail: detail.to_string(),
+                extensions: Default::default(),
+            };
+        };
+        AppError::Conflict {
+            detail: detail.to_string(),
+            extensions,
+        }
+    }
+
+    /// The schema constraint a write broke, if that's what failed.
+    fn violation(&self) -> Option<&dyn DatabaseError> {
+        match self {
+            AppError::Database(sqlx::Error::Database(e)) if e.kind() != ErrorKind::Other => {
+                Some(e.as_ref())
+            }
+            _ => None,
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        if let Some(e) = self.violation() {
+            return match e.kind() {
+                ErrorKind::UniqueViolation | ErrorKind::ForeignKeyViolation => StatusCode::CONFLICT,
+                _ => StatusCode::UNPROCESSABLE_ENTITY,
+            };
+        }
+        match self {
+            AppError::BadRequest(_) | AppError::Json(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::NotFound(_) | AppError::Database(sqlx::Error::RowNotFound) => {
+                StatusCode::NOT_FOUND
+            }
+            AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Database(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Upstream(e) if e.status() == Some(StatusCode::NOT_FOUND) => {
+                StatusCode::NOT_FOUND
+            }
+            AppError::Upstream(_) | AppError::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            AppError::Multipart(e) => e.status(),
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::Unprocessable(_) | AppError::Rows { .. } | AppError::Image(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+        }
+    }
+}
+
+macro_rules! bad_request_from {
+    ($($err:ty),* $(,)?) => {
+        $(impl From<$err> for AppError {
+            fn from(e: $err) -> Self {
+                AppError::bad_request(e)
+            }
+        })*
+    };
+}
+
+bad_request_from!(
+    std::num::ParseIntError,
+    std::num::ParseFloatError,
+    base64::DecodeError,
+    ulid::DecodeError,
+);
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        let detail = rejection.body_text();
+        match rejection.status() {
+            StatusCode::UNPROCESSABLE_ENTITY => AppError::Unprocessable(detail),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => AppError::UnsupportedMediaType(detail),
+            _ => AppError::BadRequest(detail),
+        }
+    }
+}
+
+/// `axum::Json` as an extractor, but rejecting with a problem body like every
+/// other error instead of plain text.
+pub(crate) struct JsonBody<T>(pub(crate) T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for JsonBody<T>
+where
+    Json<T>: FromRequest<S, Rejection = JsonRejection>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::from_request(req, state).await?;
+        Ok(JsonBody(value))
+    }
+}
+
+/// RFC 7807 problem details body.
+#[derive(serde::Serialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    /// Extension members, like which records of an upload failed.
+    #[serde(flatten)]
+    extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let extensions = match &self {
+            AppError::Rows { rows, .. } => {
+                serde_json::Map::from_iter([("errors".into(), serde_json::json!(rows))])
+            }
+            AppError::Conflict { extensions, .. } => extensions.clone(),
+            _ => Default::default(),
+        };
+        let detail = if let Some(e) = self.violation() {
+            // sqlite's own message names tables and columns, keep it to the kind
+            String::from(match e.kind() {
+                ErrorKind::UniqueViolation => "duplicate id",
+                ErrorKind::ForeignKeyViolation => {
+                    "unknown region, or a region that still has orders"
+                }
+                ErrorKind::NotNullViolation => "missing value",
+                _ => "invalid value",
+            })
+        } else if let AppError::Database(_) = self {
+            // don't leak queries or schema details to the client
+            tracing::error!(error = %self, "database error");
+            String::from("database error")
+        } else {
+            if status.is_server_error() {
+                tracing::error!(error = %self);
+            }
+            self.to_string()
+        };
+
+        (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(Problem {
+                kind: "about:blank",
+                title: status.canonical_reason().unwrap_or_default(),
+                status: status.as_u16(),
+                detail,
+                extensions,
+            }),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{http::StatusCode, response::IntoResponse};
+
+    use super::AppError;
+
+    #[tokio::test]
+    async fn problem_body() {
+        let res = AppError::bad_request("nope").into_response();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(res.headers()["content-type"], "application/problem+json");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Bad Request",
+                "status": 400,
+                "detail": "nope"
+            })
+        );
+    }
+
+    #[test]
+    fn status_mapping() {
+        assert_eq!(
+            AppError::Database(sqlx::Error::RowNotFound).status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            AppError::Database(sqlx::Error::PoolClosed).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            AppError::from("x".parse::<i64>().unwrap_err()).status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
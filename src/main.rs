@@ -2,6 +2,8 @@ use axum::http::StatusCode;
 use axum::{routing::get, Router};
 
 mod days;
+mod error;
+mod records;
 
 async fn hello_world() -> &'static str {
     "Hello, world!"
@@ -14,8 +16,7 @@ async fn fake_err() -> StatusCode {
 #[shuttle_runtime::main]
 async fn main() -> shuttle_axum::ShuttleAxum {
     let local_path = std::env::var("DATABASE_URL").unwrap_or(String::from("sqlite::memory:"));
-    let pool = sqlx::SqlitePool::connect(&local_path).await.unwrap();
-    sqlx::migrate!().run(&pool).await.unwrap();
+    let pool = days::pool(&local_path).await.unwrap();
 
     let router = Router::new()
         .route("/", get(hello_world))
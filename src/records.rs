@@ -0,0 +1,505 @@
+//! Upload bodies of many records, negotiated on `Content-Type`:
+//!
+//! - `application/json`: an array of objects
+//! - `text/csv`: a header row naming the fields, then one record per row
+//! - `application/x-ndjson`: one object per line
+//!
+//! Bodies are decoded as they arrive, a record at a time, so a large upload
+//! never has to be held in memory whole.
+
+use std::collections::VecDeque;
+
+use axum::{
+    async_trait,
+    body::BodyDataStream,
+    extract::{FromRequest, Request},
+    http::{header, HeaderMap},
+};
+use futures_util::{stream, Stream, StreamExt};
+use serde::de::DeserializeOwned;
+
+use crate::error::AppError;
+
+/// Rows reported back at most, a broken file shouldn't make a huge response.
+const MAX_ROW_ERRORS: usize = 100;
+/// Longest record kept, past it the rest is skipped and the row reported.
+const MAX_RECORD_BYTES: usize = 64 << 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Json,
+    Csv,
+    Ndjson,
+}
+
+impl Format {
+    fn of(headers: &HeaderMap) -> Result<Self, AppError> {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        match essence.to_ascii_lowercase().as_str() {
+            "application/json" => Ok(Format::Json),
+            "text/csv" => Ok(Format::Csv),
+            "application/x-ndjson" | "application/ndjson" => Ok(Format::Ndjson),
+            _ => Err(AppError::UnsupportedMediaType(format!(
+                "expected application/json, text/csv or application/x-ndjson, got {content_type:?}"
+            ))),
+        }
+    }
+}
+
+/// Why one record of an upload couldn't be read.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub(crate) struct RowError {
+    /// Where the record starts, counting from 1.
+    pub(crate) line: usize,
+    pub(crate) detail: String,
+}
+
+/// A request body of records in any supported format.
+pub(crate) struct Records {
+    format: Format,
+    body: BodyDataStream,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for Records {
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Records {
+            format: Format::of(req.headers())?,
+            body: req.into_body().into_data_stream(),
+        })
+    }
+}
+
+impl Records {
+    /// Each record in order, or why it couldn't be read. A broken body ends
+    /// the stream after its error.
+    pub(crate) fn decode<T: DeserializeOwned>(self) -> impl Stream<Item = Result<T, RowError>> {
+        let decoder = Decoder {
+            body: self.body,
+            splitter: Splitter::new(self.format),
+            ready: VecDeque::new(),
+            headers: None,
+            done: false,
+        };
+        stream::unfold(decoder, |mut decoder| async move {
+            loop {
+                if let Some((line, chunk)) = decoder.ready.pop_front() {
+                    match decoder.parse(line, chunk) {
+                        Some(item) => return Some((item, decoder)),
+                        None => continue,
+                    }
+                }
+                if decoder.done {
+                    return None;
+                }
+                match decoder.body.next().await {
+                    Some(Ok(bytes)) => decoder.splitter.feed(&bytes, &mut decoder.ready),
+                    Some(Err(e)) => {
+                        decoder.done = true;
+                        let line = decoder.splitter.line;
+                        return Some((Err(row_error(line, e)), decoder));
+                    }
+                    None => {
+                        decoder.done = true;
+                        decoder.splitter.finish(&mut decoder.ready);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Every record, or all the rows that couldn't be read.
+    pub(crate) async fn collect<T: DeserializeOwned>(self) -> Result<Vec<T>, AppError> {
+        let mut records = Vec::new();
+        let mut errors = Errors::default();
+        let mut decoded = std::pin::pin!(self.decode());
+        while let Some(record) = decoded.next().await {
+            match record {
+                Ok(record) => records.push(record),
+                Err(e) => errors.push(e),
+            }
+        }
+        errors.into_result().map(|()| records)
+    }
+}
+
+/// Row errors gathered while working through an upload.
+#[derive(Default)]
+pub(crate) struct Errors {
+    rows: Vec<RowError>,
+    count: usize,
+}
+
+impl Errors {
+    pub(crate) fn push(&mut self, error: RowError) {
+        self.count += 1;
+        if self.rows.len() < MAX_ROW_ERRORS {
+            self.rows.push(error);
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub(crate) fn into_result(self) -> Result<(), AppError> {
+        if self.count == 0 {
+            return Ok(());
+        }
+        Err(AppError::Rows {
+            count: self.count,
+            rows: self.rows,
+        })
+    }
+}
+
+fn row_error(line: usize, detail: impl ToString) -> RowError {
+    RowError {
+        line,
+        detail: detail.to_string(),
+    }
+}
+
+type Chunk = (usize, Result<Vec<u8>, String>);
+
+struct Decoder {
+    body: BodyDataStream,
+    splitter: Splitter,
+    ready: VecDeque<Chunk>,
+    /// The csv header row, once read.
+    headers: Option<csv::StringRecord>,
+    done: bool,
+}
+
+impl Decoder {
+    /// `None` for the csv header row, which isn't a record.
+    fn parse<T: DeserializeOwned>(
+        &mut self,
+        line: usize,
+        chunk: Result<Vec<u8>, String>,
+    ) -> Option<Result<T, RowError>> {
+        let bytes = match chunk {
+            Ok(bytes) => bytes,
+            Err(detail) => return Some(Err(row_error(line, detail))),
+        };
+        if self.splitter.format != Format::Csv {
+            return Some(serde_json::from_slice(&bytes).map_err(|e| {
+                // serde_json counts from the start of the record
+                let message = e.to_string();
+                let detail = message.split(" at line ").next().unwrap_or_default();
+                row_error(line + e.line().saturating_sub(1), detail)
+            }));
+        }
+
+        let record = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .trim(csv::Trim::All)
+            .from_reader(bytes.as_slice())
+            .into_records()
+            .next()?;
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => return Some(Err(row_error(line, e))),
+        };
+        let Some(headers) = &self.headers else {
+            self.headers = Some(record);
+            return None;
+        };
+        Some(record.deserialize(Some(headers)).map_err(|e| {
+            let detail = match e.kind() {
+                csv::ErrorKind::Deserialize { err, .. } => {
+                    let field = err.field().and_then(|i| headers.get(i as usize));
+                    match field {
+                        Some(field) => format!("{field}: {}", err.kind()),
+                        None => err.kind().to_string(),
+                    }
+                }
+                _ => e.to_string(),
+            };
+            row_error(line, detail)
+        }))
+    }
+}
+
+#[derive(PartialEq)]
+enum ArrayState {
+    Before,
+    Inside,
+    After,
+    Broken,
+}
+
+/// Cuts a body into one chunk of bytes per record as it arrives.
+struct Splitter {
+    format: Format,
+    /// The line being read.
+    line: usize,
+    /// The line the record in `buf` starts on.
+    start: usize,
+    buf: Vec<u8>,
+    /// The record in `buf` went past [`MAX_RECORD_BYTES`] and was cut short.
+    oversized: bool,
+    /// Inside a quoted csv field or a json string.
+    quoted: bool,
+    escaped: bool,
+    /// Json nesting, 1 directly inside the array.
+    depth: usize,
+    array: ArrayState,
+    /// A comma was read and no element has followed it yet.
+    after_comma: bool,
+}
+
+impl Splitter {
+    fn new(format: Format) -> Self {
+        Splitter {
+            format,
+            line: 1,
+            start: 1,
+            buf: Vec::new(),
+            oversized: false,
+            quoted: false,
+            escaped: false,
+            depth: 0,
+            array: ArrayState::Before,
+            after_comma: false,
+        }
+    }
+
+    fn feed(&mut self, bytes: &[u8], ready: &mut VecDeque<Chunk>) {
+        for &byte in bytes {
+            match self.format {
+                Format::Ndjson => self.ndjson(byte, ready),
+                Format::Csv => self.csv(byte, ready),
+                Format::Json => self.json(byte, ready),
+            }
+            if byte == b'\n' {
+                self.line += 1;
+            }
+        }
+    }
+
+    fn finish(&mut self, ready: &mut VecDeque<Chunk>) {
+        match self.format {
+            Format::Ndjson => self.emit(ready),
+            Format::Csv if self.quoted => {
+                ready.push_back((self.start, Err("unterminated quoted field".into())))
+            }
+            Format::Csv => self.emit(ready),
+            Format::Json => match self.array {
+                ArrayState::After | ArrayState::Broken => {}
+                ArrayState::Before => {
+                    ready.push_back((self.line, Err("expected a json array".into())))
+                }
+                ArrayState::Inside => {
+                    ready.push_back((self.line, Err("unterminated json array".into())))
+                }
+            },
+        }
+    }
+
+    /// Hands over the record in `buf`, skipping blank ones.
+    fn emit(&mut self, ready: &mut VecDeque<Chunk>) {
+        let record = std::mem::take(&mut self.buf);
+        if std::mem::take(&mut self.oversized) {
+            let detail = format!("record is longer than {MAX_RECORD_BYTES} bytes");
+            ready.push_back((self.start, Err(detail)));
+        } else if !record.trim_ascii().is_empty() {
+            ready.push_back((self.start, Ok(record)));
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.buf.is_empty() {
+            if byte.is_ascii_whitespace() {
+                return;
+            }
+            self.start = self.line;
+        }
+        if self.buf.len() < MAX_RECORD_BYTES {
+            self.buf.push(byte);
+        } else {
+            self.oversized = true;
+        }
+    }
+
+    fn ndjson(&mut self, byte: u8, ready: &mut VecDeque<Chunk>) {
+        match byte {
+            b'\n' => self.emit(ready),
+            _ => self.push(byte),
+        }
+    }
+
+    fn csv(&mut self, byte: u8, ready: &mut VecDeque<Chunk>) {
+        match byte {
+            b'\n' if !self.quoted => self.emit(ready),
+            _ => {
+                // an escaped quote ("") toggles twice, leaving it quoted
+                if byte == b'"' {
+                    self.quoted = !self.quoted;
+                }
+                self.push(byte);
+            }
+        }
+    }
+
+    fn json(&mut self, byte: u8, ready: &mut VecDeque<Chunk>) {
+        match self.array {
+            ArrayState::Broken => {}
+            ArrayState::Before | ArrayState::After if byte.is_ascii_whitespace() => {}
+            ArrayState::Before if byte == b'[' => {
+                self.array = ArrayState::Inside;
+                self.depth = 1;
+            }
+            ArrayState::Before => self.broken("expected a json array", ready),
+            ArrayState::After => self.broken("trailing characters after the array", ready),
+            ArrayState::Inside if self.quoted => {
+                self.push(byte);
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.quoted = false,
+                    _ => {}
+                }
+            }
+            ArrayState::Inside => match byte {
+                b',' | b']' if self.depth == 1 => {
+                    let empty = self.buf.is_empty();
+                    if empty && (byte == b',' || self.after_comma) {
+                        return self.broken("expected an array element", ready);
+                    }
+                    self.after_comma = byte == b',';
+                    self.emit(ready);
+                    if byte == b']' {
+                        self.array = ArrayState::After;
+                    }
+                }
+                b'}' if self.depth == 1 => self.broken("unbalanced braces", ready),
+                b'{' | b'[' => {
+                    self.depth += 1;
+                    self.push(byte);
+                }
+                b'}' | b']' => {
+                    self.depth -= 1;
+                    self.push(byte);
+                }
+                b'"' => {
+                    self.quoted = true;
+                    self.push(byte);
+                }
+                _ => self.push(byte),
+            },
+        }
+    }
+
+    fn broken(&mut self, detail: &str, ready: &mut VecDeque<Chunk>) {
+        self.array = ArrayState::Broken;
+        self.buf.clear();
+        self.oversized = false;
+        ready.push_back((self.line, Err(detail.into())));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use super::{Format, Splitter, MAX_RECORD_BYTES};
+
+    /// Splits `body` fed in chunks of `size` bytes.
+    fn split(format: Format, body: &str, size: usize) -> Vec<(usize, Result<String, String>)> {
+        let mut splitter = Splitter::new(format);
+        let mut ready = VecDeque::new();
+        for chunk in body.as_bytes().chunks(size) {
+            splitter.feed(chunk, &mut ready);
+        }
+        splitter.finish(&mut ready);
+        ready
+            .into_iter()
+            .map(|(line, chunk)| (line, chunk.map(|b| String::from_utf8(b).unwrap())))
+            .collect()
+    }
+
+    #[test]
+    fn json_elements_across_chunks() {
+        let body = "[\n  {\"a\": [1, 2], \"b\": \"],\\\"\"},\n  {\"a\": {}}\n]\n";
+        for size in [1, 3, 1024] {
+            assert_eq!(
+                split(Format::Json, body, size),
+                [
+                    (2, Ok("{\"a\": [1, 2], \"b\": \"],\\\"\"}".into())),
+                    (3, Ok("{\"a\": {}}\n".into()))
+                ]
+            );
+        }
+        assert!(split(Format::Json, "[]", 1).is_empty());
+        assert_eq!(
+            split(Format::Json, "[1,,2]", 1)[1],
+            (1, Err("expected an array element".into()))
+        );
+        assert_eq!(
+            split(Format::Json, "[1,]", 1)[1],
+            (1, Err("expected an array element".into()))
+        );
+        assert_eq!(
+            split(Format::Json, "{}", 1),
+            [(1, Err("expected a json array".into()))]
+        );
+        assert_eq!(
+            split(Format::Json, "[1]\n[2]", 1)[1],
+            (2, Err("trailing characters after the array".into()))
+        );
+        assert_eq!(
+            split(Format::Json, "[1,\n2", 1)[1],
+            (2, Err("unterminated json array".into()))
+        );
+    }
+
+    #[test]
+    fn csv_quoted_newlines() {
+        let body = "id,name\r\n1,\"North\nPole\"\r\n\r\n2,\"Say \"\"hi\"\"\"";
+        assert_eq!(
+            split(Format::Csv, body, 2),
+            [
+                (1, Ok("id,name\r".into())),
+                (2, Ok("1,\"North\nPole\"\r".into())),
+                (5, Ok("2,\"Say \"\"hi\"\"\"".into()))
+            ]
+        );
+        assert_eq!(
+            split(Format::Csv, "id\n\"oops", 4)[1],
+            (2, Err("unterminated quoted field".into()))
+        );
+    }
+
+    #[test]
+    fn ndjson_lines() {
+        assert_eq!(
+            split(Format::Ndjson, "{\"a\":1}\n\n  {\"a\":2}", 5),
+            [(1, Ok("{\"a\":1}".into())), (3, Ok("{\"a\":2}".into()))]
+        );
+    }
+
+    #[test]
+    fn oversized_records() {
+        let long = "1".repeat(MAX_RECORD_BYTES + 1);
+        let error = Err(format!("record is longer than {MAX_RECORD_BYTES} bytes"));
+        assert_eq!(
+            split(Format::Ndjson, &format!("{long}\n{{\"a\":1}}"), 4096),
+            [(1, error.clone()), (2, Ok("{\"a\":1}".into()))]
+        );
+        assert_eq!(
+            split(Format::Json, &format!("[\"{long}\", 2]"), 4096),
+            [(1, error), (1, Ok("2".into()))]
+        );
+        // an element that never ends is cut short instead of growing
+        let mut splitter = Splitter::new(Format::Ndjson);
+        splitter.feed(long.repeat(4).as_bytes(), &mut VecDeque::new());
+        assert_eq!(splitter.buf.len(), MAX_RECORD_BYTES);
+    }
+}